@@ -0,0 +1,258 @@
+use adw::subclass::prelude::ObjectSubclassIsExt;
+use gtk::{
+    gio,
+    glib::{self, object::CastNone, Object},
+    prelude::{
+        ActionGroupExt, ObjectExt, ActionMapExtManual, ApplicationCommandLineExt, ApplicationExt,
+        ApplicationExtManual, Cast, GtkWindowExt, ToVariant, WidgetExt,
+    },
+};
+
+use crate::notification_server::NotificationServer;
+use crate::{drawer, panel, popups};
+
+/// Command line options, each forwarded to the primary instance as an action.
+const OPTIONS: [&str; 5] = ["toggle-panel", "show-drawer", "dnd", "reload-css", "quit"];
+
+mod inner {
+    use std::cell::OnceCell;
+
+    use super::*;
+
+    use adw::subclass::{application::AdwApplicationImpl, prelude::*};
+
+    #[derive(Default)]
+    pub struct ShellApplication {
+        pub server: OnceCell<NotificationServer>,
+        pub panel: glib::WeakRef<panel::Panel>,
+        pub user_css: gtk::CssProvider,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ShellApplication {
+        const NAME: &'static str = "ShellApplication";
+        type Type = super::ShellApplication;
+        type ParentType = adw::Application;
+    }
+
+    impl ObjectImpl for ShellApplication {}
+
+    impl ApplicationImpl for ShellApplication {
+        fn startup(&self) {
+            self.parent_startup();
+            crate::load_css();
+
+            let display = gtk::gdk::Display::default().expect("Could not connect to a display.");
+            gtk::style_context_add_provider_for_display(
+                &display,
+                &self.user_css,
+                gtk::STYLE_PROVIDER_PRIORITY_USER,
+            );
+            crate::load_user_css(&self.user_css);
+            self.obj().add_app_actions();
+
+            // the server lives as long as the application, windows only ever borrow it
+            let server = NotificationServer::new();
+            server.add_actions(&*self.obj());
+            server.connect_to_dbus();
+            if let Some(conn) = self.obj().dbus_connection() {
+                server.export_control(&conn);
+            }
+            server.connect_closure(
+                "notification-closed",
+                false,
+                glib::closure_local!(|_: NotificationServer, id: u32, _reason: u32| {
+                    crate::image_cache::forget(id);
+                }),
+            );
+            let _ = self.server.set(server);
+        }
+
+        fn activate(&self) {
+            self.parent_activate();
+
+            // running `shell` again brings the existing panel to the front
+            if let Some(panel) = self.panel.upgrade() {
+                panel.raise();
+                return;
+            }
+
+            let obj = self.obj();
+            let app = obj.upcast_ref::<adw::Application>();
+
+            let panel = panel::Panel::new(app);
+            self.panel.set(Some(&panel));
+            panel.present();
+
+            // both stay unmapped until they are needed
+            let _popups = popups::PopupWindow::new(app);
+            let _drawer = drawer::NotificationDrawer::new(app);
+        }
+
+        /// Runs on the primary instance, also for invocations forwarded from a
+        /// second `shell` process.
+        fn command_line(&self, command_line: &gio::ApplicationCommandLine) -> glib::ExitCode {
+            let obj = self.obj();
+            let options = command_line.options_dict();
+
+            // the first invocation brings up the shell, later ones only run their options
+            let has_options = OPTIONS.iter().any(|option| options.contains(option));
+            if self.panel.upgrade().is_none() || !has_options {
+                obj.activate();
+            }
+
+            match obj.handle_options(&options) {
+                Ok(()) => glib::ExitCode::SUCCESS,
+                Err(e) => {
+                    printerr(command_line, &format!("{e}\n"));
+                    glib::ExitCode::FAILURE
+                }
+            }
+        }
+    }
+    impl GtkApplicationImpl for ShellApplication {}
+    impl AdwApplicationImpl for ShellApplication {}
+}
+
+glib::wrapper! {
+    pub struct ShellApplication(ObjectSubclass<inner::ShellApplication>)
+    @extends adw::Application, gtk::Application, gio::Application,
+    @implements gio::ActionGroup, gio::ActionMap;
+}
+
+impl ShellApplication {
+    pub fn new(application_id: &str, flags: gio::ApplicationFlags) -> Self {
+        let obj: Self = Object::builder()
+            .property("application-id", application_id)
+            .property("flags", flags)
+            .build();
+        obj.add_options();
+        obj
+    }
+
+    fn add_options(&self) {
+        let flag = |name: &str, description: &str| {
+            self.add_main_option(
+                name,
+                glib::Char::from(0),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::None,
+                description,
+                None,
+            );
+        };
+        flag("toggle-panel", "Raise or lower the panel");
+        flag("show-drawer", "Open the notification drawer");
+        flag("reload-css", "Reload the user stylesheet");
+        flag("quit", "Quit the running shell");
+
+        self.add_main_option(
+            "dnd",
+            glib::Char::from(0),
+            glib::OptionFlags::NONE,
+            glib::OptionArg::String,
+            "Change Do Not Disturb",
+            Some("on|off|toggle"),
+        );
+    }
+
+    /// Turns command line options into activations of the matching actions, so
+    /// they behave exactly like `gapplication action`.
+    fn handle_options(&self, options: &glib::VariantDict) -> Result<(), String> {
+        if let Some(state) = options.lookup::<String>("dnd").ok().flatten() {
+            match state.as_str() {
+                "on" => self.change_action_state("dnd", &true.to_variant()),
+                "off" => self.change_action_state("dnd", &false.to_variant()),
+                "toggle" => self.activate_action("dnd", None),
+                _ => return Err(format!("Unknown Do Not Disturb state {state}, expected on, off or toggle")),
+            }
+        }
+
+        for (option, action) in [
+            ("toggle-panel", "toggle-panel"),
+            ("show-drawer", "show-drawer"),
+            ("reload-css", "reload-css"),
+            ("quit", "quit"),
+        ] {
+            if options.contains(option) {
+                self.activate_action(action, None);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Registers `show-panel`, `hide-panel`, `toggle-panel`, `reload-css` and
+    /// `quit`. Like every application action they are exported through
+    /// org.gtk.Actions as well.
+    fn add_app_actions(&self) {
+        let show = gio::ActionEntry::builder("show-panel")
+            .activate(|app: &Self, _, _| app.show_panel())
+            .build();
+        let hide = gio::ActionEntry::builder("hide-panel")
+            .activate(|app: &Self, _, _| {
+                if let Some(panel) = app.imp().panel.upgrade() {
+                    panel.lower();
+                }
+            })
+            .build();
+        let toggle = gio::ActionEntry::builder("toggle-panel")
+            .activate(|app: &Self, _, _| match app.imp().panel.upgrade() {
+                Some(panel) => panel.toggle(),
+                None => app.show_panel(),
+            })
+            .build();
+        let reload_css = gio::ActionEntry::builder("reload-css")
+            .activate(|app: &Self, _, _| crate::load_user_css(&app.imp().user_css))
+            .build();
+
+        let quit = gio::ActionEntry::builder("quit")
+            .activate(|app: &Self, _, _| app.quit())
+            .build();
+
+        self.add_action_entries([show, hide, toggle, reload_css, quit]);
+    }
+
+    /// Raises the panel in front of everything, see `Panel::raise`.
+    fn show_panel(&self) {
+        // the windows are only built on the first activation
+        if self.imp().panel.upgrade().is_none() {
+            self.activate();
+        }
+        if let Some(panel) = self.imp().panel.upgrade() {
+            panel.raise();
+        }
+    }
+
+    /// The notification server owned by this application, available after `startup`.
+    pub fn notification_server(&self) -> Option<NotificationServer> {
+        self.imp().server.get().cloned()
+    }
+}
+
+/// The notification server of the running application. Widgets subscribe to its
+/// signals instead of holding a server of their own.
+pub fn notification_server() -> Option<NotificationServer> {
+    gio::Application::default()
+        .and_downcast::<ShellApplication>()
+        .and_then(|app| app.notification_server())
+}
+
+/// Prints to the stderr of the process that ran the command line, which is a
+/// different one for forwarded invocations. The variadic printerr has no
+/// binding and printerr_literal needs GLib 2.80.
+fn printerr(command_line: &gio::ApplicationCommandLine, message: &str) {
+    use glib::translate::ToGlibPtr;
+
+    let Ok(message) = std::ffi::CString::new(message) else {
+        return;
+    };
+    // SAFETY: "%s" consumes exactly the one NUL-terminated string passed
+    unsafe {
+        gio::ffi::g_application_command_line_printerr(
+            command_line.to_glib_none().0,
+            c"%s".as_ptr(),
+            message.as_ptr(),
+        );
+    }
+}
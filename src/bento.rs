@@ -9,7 +9,7 @@ mod inner {
         subclass::{prelude::*, widget::WidgetImpl},
     };
 
-    use crate::{notification_server, notifications, time};
+    use crate::{notifications, time};
 
     use super::*;
     #[derive(CompositeTemplate, Default)]
@@ -23,8 +23,6 @@ mod inner {
 
         #[template_child(id = "grid")]
         pub grid: TemplateChild<gtk::Grid>,
-
-        server: Option<notification_server::NotificationServer>,
     }
 
     #[glib::object_subclass]
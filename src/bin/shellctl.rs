@@ -0,0 +1,329 @@
+//! Command-line client for a running shell, talking to it over the session bus.
+
+use gtk::gio;
+use gtk::glib::{self, variant::ToVariant, Variant, VariantDict};
+use std::process::ExitCode;
+
+const APP_ID: &str = "io.github.johannes.shell";
+const ACTIONS_PATH: &str = "/io/github/johannes/shell";
+const CONTROL_PATH: &str = "/io/github/johannes/shell/Notifications";
+const CONTROL_INTERFACE: &str = "io.github.johannes.shell.Notifications";
+const TIMEOUT_MS: i32 = 5000;
+
+/// The operation was sent but did not succeed.
+const EXIT_FAILED: u8 = 1;
+/// Nothing owns the shell's bus name.
+const EXIT_NOT_RUNNING: u8 = 2;
+/// Mirrors EX_USAGE from sysexits.h.
+const EXIT_USAGE: u8 = 64;
+
+const USAGE: &str = "\
+Usage: shellctl [--json] <command>
+
+Commands:
+  list                  show current notifications
+  dismiss <id>|--all    dismiss one or all notifications
+  invoke <id> <key>     invoke one of the actions a notification offers
+  dnd [on|off|toggle]   show or change Do Not Disturb
+  panel show|hide|toggle
+  reload css|config     reload the user stylesheet or the notification rules
+  rules                 show how often each notification rule matched
+  history               dump all stored notifications as JSON
+
+Exit codes: 0 success, 1 operation failed, 2 shell not running, 64 usage error";
+
+/// (id, app_name, app_icon, summary, body, actions, hints, expire_timeout,
+/// timestamp, updated, silent, restored), see control-introspect.xml
+type Entry = (
+    u32,
+    String,
+    String,
+    String,
+    String,
+    Vec<String>,
+    VariantDict,
+    i32,
+    i64,
+    i64,
+    bool,
+    bool,
+);
+
+enum Error {
+    NotRunning,
+    Failed(String),
+    Usage(String),
+}
+
+impl From<glib::Error> for Error {
+    fn from(e: glib::Error) -> Self {
+        if e.matches(gio::DBusError::ServiceUnknown) || e.matches(gio::DBusError::NameHasNoOwner) {
+            return Error::NotRunning;
+        }
+        Error::Failed(e.to_string())
+    }
+}
+
+fn main() -> ExitCode {
+    let mut json = false;
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| {
+            let flag = arg == "--json";
+            json |= flag;
+            !flag
+        })
+        .collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match run(&args, json) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::NotRunning) => {
+            eprintln!("shellctl: the shell is not running");
+            ExitCode::from(EXIT_NOT_RUNNING)
+        }
+        Err(Error::Failed(e)) => {
+            eprintln!("shellctl: {e}");
+            ExitCode::from(EXIT_FAILED)
+        }
+        Err(Error::Usage(e)) => {
+            eprintln!("shellctl: {e}\n\n{USAGE}");
+            ExitCode::from(EXIT_USAGE)
+        }
+    }
+}
+
+fn run(args: &[&str], json: bool) -> Result<(), Error> {
+    let Some((command, rest)) = args.split_first() else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+
+    match (*command, rest) {
+        ("list", []) => {
+            let entries = list("List")?;
+            if json {
+                println!("{}", entries_json(&entries));
+            } else {
+                print_entries(&entries);
+            }
+        }
+        ("history", []) => {
+            println!("{}", entries_json(&list("GetHistory")?));
+        }
+        ("dismiss", ["--all"]) => {
+            call(CONTROL_PATH, CONTROL_INTERFACE, "DismissAll", None)?;
+        }
+        ("dismiss", [id]) => {
+            let id: u32 = id
+                .parse()
+                .map_err(|_| Error::Usage(format!("not a notification id: {id}")))?;
+            let reply = call(CONTROL_PATH, CONTROL_INTERFACE, "Dismiss", Some(&(id,).to_variant()))?;
+            if reply.get::<(bool,)>() != Some((true,)) {
+                return Err(Error::Failed(format!("no notification with id {id}")));
+            }
+        }
+        ("invoke", [id, key]) => {
+            let id: u32 = id
+                .parse()
+                .map_err(|_| Error::Usage(format!("not a notification id: {id}")))?;
+            let reply = call(CONTROL_PATH, CONTROL_INTERFACE, "InvokeAction", Some(&(id, *key).to_variant()))?;
+            if reply.get::<(bool,)>() != Some((true,)) {
+                return Err(Error::Failed(format!("notification {id} has no action {key}")));
+            }
+        }
+        ("dnd", []) => {
+            let (enabled, missed) = dnd()?;
+            if json {
+                println!("{{\"enabled\":{enabled},\"missed\":{missed}}}");
+            } else {
+                let state = if enabled { "on" } else { "off" };
+                println!("Do Not Disturb is {state}, {missed} missed");
+            }
+        }
+        // activating the stateful action flips it in the shell, where nothing
+        // else can change it between reading and writing the state
+        ("dnd", ["toggle"]) => activate_action("dnd")?,
+        ("dnd", [state]) => {
+            let enabled = match *state {
+                "on" => true,
+                "off" => false,
+                _ => return Err(Error::Usage(format!("unknown dnd state: {state}"))),
+            };
+            call(CONTROL_PATH, CONTROL_INTERFACE, "SetDnd", Some(&(enabled,).to_variant()))?;
+        }
+        ("panel", [op @ ("show" | "hide" | "toggle")]) => {
+            activate_action(&format!("{op}-panel"))?;
+        }
+        ("rules", []) => {
+            let hits = rule_hits()?;
+            if json {
+                println!("{}", rule_hits_json(&hits));
+            } else if hits.is_empty() {
+                println!("No notification rules");
+            } else {
+                for (name, hits) in hits {
+                    println!("{hits:>8}  {name}");
+                }
+            }
+        }
+        ("reload", ["css"]) => activate_action("reload-css")?,
+        ("reload", ["config"]) => activate_action("reload-rules")?,
+        ("help" | "--help" | "-h", _) => println!("{USAGE}"),
+        _ => return Err(Error::Usage(format!("unknown command: {}", args.join(" ")))),
+    }
+
+    Ok(())
+}
+
+fn call(path: &str, interface: &str, method: &str, args: Option<&Variant>) -> Result<Variant, Error> {
+    let conn = gio::bus_get_sync(gio::BusType::Session, None::<&gio::Cancellable>)?;
+    let reply = conn.call_sync(
+        Some(APP_ID),
+        path,
+        interface,
+        method,
+        args,
+        None,
+        // never start the shell just to ask it something
+        gio::DBusCallFlags::NO_AUTO_START,
+        TIMEOUT_MS,
+        None::<&gio::Cancellable>,
+    )?;
+    Ok(reply)
+}
+
+/// Activates one of the application's GActions through org.gtk.Actions.
+fn activate_action(name: &str) -> Result<(), Error> {
+    let args = (name, Vec::<Variant>::new(), VariantDict::new(None)).to_variant();
+    call(ACTIONS_PATH, "org.gtk.Actions", "Activate", Some(&args))?;
+    Ok(())
+}
+
+fn dnd() -> Result<(bool, u32), Error> {
+    call(CONTROL_PATH, CONTROL_INTERFACE, "GetDnd", None)?
+        .get::<(bool, u32)>()
+        .ok_or_else(|| Error::Failed("unexpected reply to GetDnd".into()))
+}
+
+fn list(method: &str) -> Result<Vec<Entry>, Error> {
+    call(CONTROL_PATH, CONTROL_INTERFACE, method, None)?
+        .get::<(Vec<Entry>,)>()
+        .map(|(entries,)| entries)
+        .ok_or_else(|| Error::Failed(format!("unexpected reply to {method}")))
+}
+
+fn rule_hits() -> Result<Vec<(String, u64)>, Error> {
+    call(CONTROL_PATH, CONTROL_INTERFACE, "GetRuleHits", None)?
+        .get::<(Vec<(String, u64)>,)>()
+        .map(|(hits,)| hits)
+        .ok_or_else(|| Error::Failed("unexpected reply to GetRuleHits".into()))
+}
+
+fn format_time(unix: i64) -> String {
+    glib::DateTime::from_unix_local(unix)
+        .and_then(|dt| dt.format("%X"))
+        .map(|s| s.to_string())
+        .unwrap_or_default()
+}
+
+fn print_entries(entries: &[Entry]) {
+    if entries.is_empty() {
+        println!("No notifications");
+        return;
+    }
+    for (id, app_name, _, summary, body, _, _, _, timestamp, ..) in entries {
+        let timestamp = format_time(*timestamp);
+        println!("{id:>5}  {timestamp}  {app_name}: {summary}");
+        if let Some(line) = body.lines().next().filter(|line| !line.is_empty()) {
+            println!("       {line}");
+        }
+    }
+}
+
+fn entries_json(entries: &[Entry]) -> String {
+    let items: Vec<String> = entries.iter().map(entry_json).collect();
+    format!("[{}]", items.join(","))
+}
+
+fn entry_json(entry: &Entry) -> String {
+    let (id, app_name, app_icon, summary, body, actions, hints, expire_timeout, timestamp, updated, silent, restored) =
+        entry;
+
+    let actions: Vec<String> = actions
+        .chunks_exact(2)
+        .map(|pair| format!("{{\"key\":{},\"label\":{}}}", json_string(&pair[0]), json_string(&pair[1])))
+        .collect();
+
+    format!(
+        "{{\"id\":{id},\"app_name\":{},\"app_icon\":{},\"summary\":{},\"body\":{},\"actions\":[{}],\"hints\":{},\"expire_timeout\":{expire_timeout},\"timestamp\":{timestamp},\"updated\":{},\"silent\":{silent},\"restored\":{restored}}}",
+        json_string(app_name),
+        json_string(app_icon),
+        json_string(summary),
+        json_string(body),
+        actions.join(","),
+        hints_json(hints),
+        if *updated == 0 { "null".to_string() } else { updated.to_string() },
+    )
+}
+
+fn rule_hits_json(hits: &[(String, u64)]) -> String {
+    let items: Vec<String> = hits
+        .iter()
+        .map(|(name, hits)| format!("{{\"rule\":{},\"hits\":{hits}}}", json_string(name)))
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Simple hints only, image data and other containers are left out.
+fn hints_json(hints: &VariantDict) -> String {
+    let dict = hints.end();
+    let fields: Vec<String> = dict
+        .iter()
+        .filter_map(|entry| {
+            let key = entry.child_value(0).get::<String>()?;
+            let value = entry.child_value(1).as_variant()?;
+            Some(format!("{}:{}", json_string(&key), variant_json(&value)?))
+        })
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn variant_json(value: &Variant) -> Option<String> {
+    if let Some(s) = value.str() {
+        return Some(json_string(s));
+    }
+    if let Some(b) = value.get::<bool>() {
+        return Some(b.to_string());
+    }
+    [
+        value.get::<u8>().map(i64::from),
+        value.get::<i16>().map(i64::from),
+        value.get::<u16>().map(i64::from),
+        value.get::<i32>().map(i64::from),
+        value.get::<u32>().map(i64::from),
+        value.get::<i64>(),
+    ]
+    .into_iter()
+    .flatten()
+    .next()
+    .map(|n| n.to_string())
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
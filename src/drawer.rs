@@ -0,0 +1,130 @@
+use gtk::{
+    gdk, gio,
+    glib::{self, clone, Object},
+    prelude::{ActionMapExtManual, GtkWindowExt, WidgetExt},
+};
+use gtk4_layer_shell::{Edge, KeyboardMode, Layer, LayerShell};
+
+use crate::notifications::NotificationsModule;
+
+mod inner {
+    use super::*;
+
+    use gtk::subclass::{prelude::*, widget::WidgetImpl, window::WindowImpl};
+    use gtk::CompositeTemplate;
+
+    #[derive(CompositeTemplate, Default)]
+    #[template(resource = "/shell/ui/drawer.ui")]
+    pub struct NotificationDrawer {
+        #[template_child(id = "notifications-module")]
+        pub notifications_module: TemplateChild<NotificationsModule>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for NotificationDrawer {
+        const NAME: &'static str = "NotificationDrawer";
+        type Type = super::NotificationDrawer;
+        type ParentType = gtk::ApplicationWindow;
+
+        fn new() -> Self {
+            Self {
+                ..Default::default()
+            }
+        }
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for NotificationDrawer {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+
+            obj.init_layer_shell();
+            obj.set_layer(Layer::Overlay);
+            obj.set_anchor(Edge::Top, true);
+            obj.set_anchor(Edge::Bottom, true);
+            obj.set_anchor(Edge::Right, true);
+            // on demand, so clicking anywhere else takes the focus away
+            obj.set_keyboard_mode(KeyboardMode::OnDemand);
+
+            let keys = gtk::EventControllerKey::new();
+            keys.connect_key_pressed(clone!(
+                #[weak]
+                obj,
+                #[upgrade_or]
+                glib::Propagation::Proceed,
+                move |_, key, _, _| {
+                    if key != gdk::Key::Escape {
+                        return glib::Propagation::Proceed;
+                    }
+                    obj.set_visible(false);
+                    glib::Propagation::Stop
+                }
+            ));
+            obj.add_controller(keys);
+
+            obj.connect_is_active_notify(|obj| {
+                if !obj.is_active() {
+                    obj.set_visible(false);
+                }
+            });
+        }
+    }
+    impl WidgetImpl for NotificationDrawer {}
+    impl WindowImpl for NotificationDrawer {}
+    impl ApplicationWindowImpl for NotificationDrawer {}
+}
+
+glib::wrapper! {
+    pub struct NotificationDrawer(ObjectSubclass<inner::NotificationDrawer>)
+    @extends gtk::ApplicationWindow, gtk::Window, gtk::Widget,
+    @implements gio::ActionGroup, gio::ActionMap, gtk::Accessible, gtk::Buildable,
+                gtk::ConstraintTarget, gtk::Native, gtk::Root, gtk::ShortcutManager;
+}
+
+impl NotificationDrawer {
+    pub fn new(app: &adw::Application) -> Self {
+        let obj: NotificationDrawer = Object::new();
+        obj.set_application(Some(app));
+        obj.add_app_actions(app);
+        obj
+    }
+
+    /// Registers `show-drawer`, `hide-drawer` and `toggle-drawer` on the application.
+    fn add_app_actions(&self, app: &adw::Application) {
+        let show = gio::ActionEntry::builder("show-drawer")
+            .activate(clone!(
+                #[weak(rename_to = drawer)]
+                self,
+                move |_: &adw::Application, _, _| drawer.present()
+            ))
+            .build();
+        let hide = gio::ActionEntry::builder("hide-drawer")
+            .activate(clone!(
+                #[weak(rename_to = drawer)]
+                self,
+                move |_: &adw::Application, _, _| drawer.set_visible(false)
+            ))
+            .build();
+        let toggle = gio::ActionEntry::builder("toggle-drawer")
+            .activate(clone!(
+                #[weak(rename_to = drawer)]
+                self,
+                move |_: &adw::Application, _, _| {
+                    if drawer.is_visible() {
+                        drawer.set_visible(false);
+                    } else {
+                        drawer.present();
+                    }
+                }
+            ))
+            .build();
+
+        app.add_action_entries([show, hide, toggle]);
+    }
+}
@@ -0,0 +1,132 @@
+use gtk::{gdk, glib};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::OnceLock;
+
+use crate::notification_server::{DecodedImage, ImageLimits, NotificationItem};
+
+/// Edge length images are decoded at, twice the displayed size for HiDPI.
+pub const IMAGE_SIZE: i32 = 128;
+const MAX_ENTRIES: usize = 256;
+
+/// Notification id and hash of the image content.
+pub type Key = (u32, u64);
+
+type Waiter = Box<dyn FnOnce(Option<gdk::Texture>)>;
+
+#[derive(Default)]
+struct ImageCache {
+    textures: HashMap<Key, gdk::Texture>,
+    /// Insertion order, the oldest texture is evicted first.
+    order: VecDeque<Key>,
+    /// Callbacks waiting for an image that is being decoded.
+    pending: HashMap<Key, Vec<Waiter>>,
+}
+
+thread_local! {
+    static CACHE: RefCell<ImageCache> = RefCell::new(ImageCache::default());
+}
+
+fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("image-decoder")
+            .build()
+            .expect("Could not start the image decoding runtime")
+    })
+}
+
+pub fn key(item: &NotificationItem) -> Option<Key> {
+    Some((item.id(), item.image_hash()?))
+}
+
+pub fn lookup(key: Key) -> Option<gdk::Texture> {
+    CACHE.with_borrow(|cache| cache.textures.get(&key).cloned())
+}
+
+/// Decodes the image of `item` within `limits` on a worker thread and calls
+/// `done` with the texture, or `None` if it could not be decoded. Concurrent
+/// requests for the same image share one decode.
+pub fn load(
+    item: &NotificationItem,
+    limits: ImageLimits,
+    done: impl FnOnce(Option<gdk::Texture>) + 'static,
+) {
+    let (Some(key), Some(source)) = (key(item), item.image_source()) else {
+        done(None);
+        return;
+    };
+    if let Some(texture) = lookup(key) {
+        done(Some(texture));
+        return;
+    }
+
+    let first = CACHE.with_borrow_mut(|cache| {
+        let waiters = cache.pending.entry(key).or_default();
+        waiters.push(Box::new(done));
+        waiters.len() == 1
+    });
+    if !first {
+        return;
+    }
+
+    let handle = runtime().spawn_blocking(move || source.decode(IMAGE_SIZE, &limits));
+    glib::spawn_future_local(async move {
+        let texture = match handle.await {
+            Ok(decoded) => decoded.map(texture_from),
+            Err(e) => {
+                eprintln!("Image decoding failed: {e}");
+                None
+            }
+        };
+
+        let waiters = CACHE.with_borrow_mut(|cache| {
+            if let Some(texture) = &texture {
+                cache.insert(key, texture.clone());
+            }
+            cache.pending.remove(&key).unwrap_or_default()
+        });
+        for done in waiters {
+            done(texture.clone());
+        }
+    });
+}
+
+/// Drops the textures of a notification that was closed.
+pub fn forget(id: u32) {
+    CACHE.with_borrow_mut(|cache| {
+        cache.textures.retain(|key, _| key.0 != id);
+        cache.order.retain(|key| key.0 != id);
+    });
+}
+
+impl ImageCache {
+    fn insert(&mut self, key: Key, texture: gdk::Texture) {
+        if self.textures.insert(key, texture).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > MAX_ENTRIES {
+            if let Some(old) = self.order.pop_front() {
+                self.textures.remove(&old);
+            }
+        }
+    }
+}
+
+fn texture_from(image: DecodedImage) -> gdk::Texture {
+    let format = if image.has_alpha {
+        gdk::MemoryFormat::R8g8b8a8
+    } else {
+        gdk::MemoryFormat::R8g8b8
+    };
+    gdk::MemoryTexture::new(
+        image.width,
+        image.height,
+        format,
+        &glib::Bytes::from_owned(image.data),
+        image.stride,
+    )
+    .into()
+}
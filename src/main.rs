@@ -1,7 +1,12 @@
+mod application;
 mod bento;
+mod drawer;
+mod image_cache;
 mod notification_server;
 mod notification_display;
+mod notification_group;
 mod panel;
+mod popups;
 mod time;
 mod utils;
 mod notifications;
@@ -13,11 +18,7 @@ const DATA_DIR: &'static str = "/home/johannes/bracket/data/";
 fn main() -> () {
     load_resources();
 
-    let app = adw::Application::new(Some(APP_ID), gio::ApplicationFlags::FLAGS_NONE);
-    app.connect_startup(|_| {
-        load_css();
-    });
-    app.connect_activate(build_ui);
+    let app = application::ShellApplication::new(APP_ID, gio::ApplicationFlags::HANDLES_COMMAND_LINE);
     app.run();
 }
 
@@ -31,10 +32,15 @@ fn load_css() {
     );
 }
 
-fn build_ui(app: &adw::Application) {
-    let panel = panel::Panel::new(&app, None);
-    
-    panel.present();
+/// Loads `$XDG_CONFIG_HOME/shell/style.css` into `provider`, which sits on top
+/// of the bundled style. Loading again picks up changes to the file.
+fn load_user_css(provider: &CssProvider) {
+    let path = gtk::glib::user_config_dir().join("shell").join("style.css");
+    if path.exists() {
+        provider.load_from_path(path);
+    } else {
+        provider.load_from_string("");
+    }
 }
 
 fn load_resources() {
@@ -1,27 +1,32 @@
+use adw::prelude::AnimationExt;
 use adw::subclass::prelude::{ObjectSubclassExt, ObjectSubclassIsExt};
 use gio::prelude::AppInfoExt;
 use gtk::{
-    gdk::prelude::TextureExt,
+    gdk, gio,
     glib::{
-        self,
-        object::ObjectExt,
+        self, clone,
+        object::{CastNone, ObjectExt},
+        variant::ToVariant,
         Object,
     },
-    prelude::{BoxExt, WidgetExt},
+    prelude::{ActionableExt, BoxExt, ButtonExt, WidgetExt},
     CompositeTemplate,
 };
 
+use crate::image_cache;
 use crate::notification_server;
+use crate::utils;
+
+const PROGRESS_ANIMATION_MS: u32 = 250;
 
 mod inner {
 
     use gtk::prelude::ObjectExt;
-    
+    use std::cell::{Cell, RefCell};
 
     use super::*;
     use gtk::glib::{self, derived_properties, Properties};
     use gtk::subclass::prelude::*;
-    use gtk::template_callbacks;
     #[derive(CompositeTemplate, Default, Properties)]
     #[properties(wrapper_type = super::NotificationDisplay)]
     #[template(resource = "/shell/ui/notification.ui")]
@@ -29,22 +34,65 @@ mod inner {
         #[template_child(id = "app-label")]
         pub app_label: TemplateChild<gtk::Label>,
 
-        // #[template_child(id = "app-icon")]
-        // pub app_icon: TemplateChild<gtk::Image>,
+        #[template_child(id = "app-icon")]
+        pub app_icon: TemplateChild<gtk::Image>,
         #[template_child(id = "time-label")]
         pub date_label: TemplateChild<gtk::Label>,
 
+        #[template_child(id = "close-button")]
+        pub close_button: TemplateChild<gtk::Button>,
+
         #[template_child(id = "title-label")]
         pub title_label: TemplateChild<gtk::Label>,
 
         #[template_child(id = "body-label")]
         pub body_label: TemplateChild<gtk::Label>,
+
+        #[template_child(id = "actions-box")]
+        pub actions_box: TemplateChild<gtk::Box>,
+
+        #[template_child(id = "image")]
+        pub image: TemplateChild<gtk::Picture>,
+
+        #[template_child(id = "progress-bar")]
+        pub progress_bar: TemplateChild<gtk::ProgressBar>,
+
+        /// Id and fraction of the last progress shown. Kept when the display is
+        /// rebound to an update of the same notification, so the bar animates
+        /// from the old value, and dropped once the value hint goes away.
+        pub last_progress: Cell<Option<(u32, f64)>>,
+        pub progress_animation: RefCell<Option<adw::TimedAnimation>>,
+
+        /// Image the display is waiting for or showing, rows are recycled so
+        /// late results for another notification are ignored.
+        pub image_key: Cell<Option<image_cache::Key>>,
+
+        /// Close the notification after one of its links was opened.
+        #[property(get, set)]
+        pub dismiss_on_link: Cell<bool>,
+
+        /// Turn bare URLs in the body into links.
+        #[property(get, set)]
+        pub linkify: Cell<bool>,
+
+        /// The notification shown. List rows get a new one on every bind, and
+        /// `None` when they are unbound.
+        #[property(get, set = Self::set_item, nullable)]
+        pub item: RefCell<Option<notification_server::NotificationItem>>,
+
+        pub bindings: RefCell<Vec<glib::Binding>>,
+
+        pub id: Cell<u32>,
     }
 
-    #[template_callbacks]
     impl NotificationDisplay {
-        #[template_callback]
-        fn on_close(&self) {}
+        fn set_item(&self, item: Option<notification_server::NotificationItem>) {
+            if *self.item.borrow() == item {
+                return;
+            }
+            self.item.replace(item.clone());
+            self.obj().bind_item(item.as_ref());
+        }
     }
 
     #[glib::object_subclass]
@@ -55,12 +103,13 @@ mod inner {
 
         fn new() -> Self {
             Self {
+                dismiss_on_link: Cell::new(true),
+                linkify: Cell::new(true),
                 ..Default::default()
             }
         }
         fn class_init(klass: &mut Self::Class) {
             klass.bind_template();
-            klass.bind_template_callbacks();
         }
         fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
             obj.init_template();
@@ -70,8 +119,18 @@ mod inner {
     impl ObjectImpl for NotificationDisplay {
         fn constructed(&self) {
             self.parent_constructed();
-            let obj = &self.obj();
-            let imp = obj.imp();
+            let obj = self.obj();
+
+            self.body_label.connect_activate_link(clone!(
+                #[weak]
+                obj,
+                #[upgrade_or]
+                glib::Propagation::Proceed,
+                move |_, uri| {
+                    obj.open_link(uri);
+                    glib::Propagation::Stop
+                }
+            ));
         }
     }
     impl BoxImpl for NotificationDisplay {}
@@ -91,36 +150,357 @@ impl NotificationDisplay {
     }
 
     pub fn set_from_notification(&self, notification: &notification_server::NotificationItem) {
+        self.set_item(Some(notification));
+    }
+
+    fn bind_item(&self, notification: Option<&notification_server::NotificationItem>) {
+        // an update of the same notification animates from the progress shown before
+        let last_progress = self
+            .imp()
+            .last_progress
+            .get()
+            .filter(|(id, _)| notification.is_some_and(|n| n.id() == *id));
+        self.clear();
+        let Some(notification) = notification else {
+            return;
+        };
         let imp = self.imp();
+        imp.last_progress.set(last_progress);
 
-        imp.app_label.set_label(&notification.app_name());
+        let linkify = self.linkify();
+        // the desktop entry's name wins over whatever the client calls itself
+        let entry_name = notification.app_info().map(|info| info.display_name().to_string());
+        imp.body_label.set_use_markup(true);
+        let bindings = vec![
+            notification
+                .bind_property("app-name", &*imp.app_label, "label")
+                .transform_to(move |_, name: String| Some(entry_name.clone().unwrap_or(name)))
+                .sync_create()
+                .build(),
+            notification
+                .bind_property("summary", &*imp.title_label, "label")
+                .sync_create()
+                .build(),
+            notification
+                .bind_property("body", &*imp.body_label, "label")
+                .transform_to(move |_, body: String| {
+                    Some(notification_server::body_to_pango(&body, linkify))
+                })
+                .sync_create()
+                .build(),
+            notification
+                .bind_property("timestamp", &*imp.date_label, "label")
+                .transform_to(|_, dt: Option<glib::DateTime>| {
+                    Some(format_time(dt).unwrap_or_default())
+                })
+                .sync_create()
+                .build(),
+            notification
+                .bind_property("updated", &*imp.date_label, "tooltip-text")
+                .transform_to(|_, dt: Option<glib::DateTime>| {
+                    Some(format_time(dt).map(|dt| format!("Updated {dt}")))
+                })
+                .sync_create()
+                .build(),
+        ];
+        imp.bindings.replace(bindings);
 
-        imp.title_label.set_label(&notification.summary());
-        imp.body_label.set_label(&notification.body());
+        imp.app_icon
+            .set_from_gicon(&utils::first_usable_icon(self, notification.app_icon_candidates()));
+
+        imp.id.set(notification.id());
+        imp.close_button
+            .set_action_target_value(Some(&notification.id().to_variant()));
 
         let hints = notification.get_hints();
+        if hints.urgency == notification_server::Urgency::Critical {
+            self.add_css_class("critical");
+        }
+        if notification.restored() {
+            self.add_css_class("restored");
+        }
 
-        match hints.urgency {
-            notification_server::Urgency::Critical => {
-                self.add_css_class("critical");
-            }
-            _ => {}
+        self.set_image(notification);
+        self.set_progress(notification.id(), hints.value);
+        self.set_actions(notification, hints.action_icons.unwrap_or(false));
+    }
+
+    fn set_progress(&self, id: u32, value: Option<i32>) {
+        let imp = self.imp();
+        let progress_bar = imp.progress_bar.get();
+
+        let Some(value) = value else {
+            imp.last_progress.set(None);
+            progress_bar.set_visible(false);
+            return;
         };
+        let fraction = value as f64 / 100.0;
+        progress_bar.set_visible(true);
+
+        let from = imp
+            .last_progress
+            .replace(Some((id, fraction)))
+            .filter(|(last_id, _)| *last_id == id)
+            .map(|(_, last)| last);
+        let Some(from) = from else {
+            progress_bar.set_fraction(fraction);
+            return;
+        };
+
+        let target = adw::PropertyAnimationTarget::new(&progress_bar, "fraction");
+        let animation = adw::TimedAnimation::new(&progress_bar, from, fraction, PROGRESS_ANIMATION_MS, target);
+        animation.play();
+        imp.progress_animation.replace(Some(animation));
+    }
+
+    /// Drops everything left over from the previous notification.
+    fn clear(&self) {
+        let imp = self.imp();
+
+        for binding in imp.bindings.take() {
+            binding.unbind();
+        }
 
-        let image = notification.get_image_square();
+        imp.id.set(0);
+        imp.close_button.set_action_target_value(None);
+        imp.app_icon.clear();
+        imp.app_label.set_label("");
+        imp.title_label.set_label("");
+        imp.body_label.set_label("");
+        imp.date_label.set_label("");
+        imp.date_label.set_tooltip_text(None);
+        self.remove_css_class("critical");
+        self.remove_css_class("restored");
 
-        if let Some(image) = image {
-            let texture = gtk::gdk::Texture::for_pixbuf(&image);
-            let picture = gtk::Picture::builder()
-                .paintable(&texture)
-                .vexpand(true)
-                .css_classes(["rounded-sm"])
+        imp.image_key.set(None);
+        self.show_texture(None);
+
+        if let Some(animation) = imp.progress_animation.take() {
+            animation.skip();
+        }
+        imp.last_progress.set(None);
+        imp.progress_bar.set_visible(false);
+
+        let actions_box = &imp.actions_box;
+        while let Some(child) = actions_box.first_child() {
+            actions_box.remove(&child);
+        }
+        actions_box.set_visible(false);
+    }
+
+    /// Shows the cached texture right away, otherwise a placeholder until the
+    /// image was decoded.
+    fn set_image(&self, notification: &notification_server::NotificationItem) {
+        let imp = self.imp();
+        let key = image_cache::key(notification);
+        imp.image_key.set(key);
+
+        let Some(key) = key else {
+            imp.image.set_visible(false);
+            imp.image.set_paintable(None::<&gdk::Paintable>);
+            return;
+        };
+        imp.image.set_visible(true);
+
+        if let Some(texture) = image_cache::lookup(key) {
+            self.show_texture(Some(texture));
+            return;
+        }
+
+        imp.image.set_paintable(None::<&gdk::Paintable>);
+        imp.image.add_css_class("placeholder");
+        let limits = crate::application::notification_server()
+            .map(|server| server.image_limits())
+            .unwrap_or_default();
+        image_cache::load(
+            notification,
+            limits,
+            clone!(
+                #[weak(rename_to = display)]
+                self,
+                move |texture| {
+                    if display.imp().image_key.get() == Some(key) {
+                        display.show_texture(texture);
+                    }
+                }
+            ),
+        );
+    }
+
+    fn show_texture(&self, texture: Option<gdk::Texture>) {
+        let image = &self.imp().image;
+        image.remove_css_class("placeholder");
+        image.set_visible(texture.is_some());
+        image.set_paintable(texture.as_ref());
+    }
+
+    fn open_link(&self, uri: &str) {
+        let window = self.root().and_downcast::<gtk::Window>();
+        gtk::UriLauncher::new(uri).launch(window.as_ref(), None::<&gio::Cancellable>, |res| {
+            if let Err(e) = res {
+                eprintln!("Could not open link: {e}");
+            }
+        });
+
+        if self.dismiss_on_link() {
+            let id = self.imp().id.get().to_variant();
+            if let Err(e) = self.activate_action("app.close-notification", Some(&id)) {
+                eprintln!("Could not dismiss notification: {e}");
+            }
+        }
+    }
+
+    fn set_actions(&self, notification: &notification_server::NotificationItem, use_icons: bool) {
+        let actions_box = &self.imp().actions_box;
+
+        while let Some(child) = actions_box.first_child() {
+            actions_box.remove(&child);
+        }
+
+        let id = notification.id();
+        // the default action is invoked by activating the notification itself
+        let actions: Vec<_> = notification
+            .get_actions()
+            .into_iter()
+            .filter(|(key, _)| key != "default")
+            .collect();
+
+        for (key, label) in &actions {
+            let button = gtk::Button::builder()
+                .action_name("app.invoke-action")
+                .action_target(&(id, key.as_str()).to_variant())
+                .tooltip_text(label.as_str())
                 .build();
 
-            self.append(&picture);
+            if use_icons {
+                button.set_icon_name(key);
+            } else {
+                button.set_label(label);
+            }
+
+            actions_box.append(&button);
         }
-        if let Some(datestr) = notification.timestamp().and_then(|dt| dt.format("%X").ok()) {
-            imp.date_label.set_label(&datestr);
+
+        actions_box.set_visible(!actions.is_empty());
+    }
+}
+
+fn format_time(dt: Option<glib::DateTime>) -> Option<String> {
+    dt?.format("%X").ok().map(|s| s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notification_server::NotificationItem;
+    use std::time::{Duration, Instant};
+
+    fn item(id: u32, summary: &str, body: &str, actions: &[&str], hints: glib::VariantDict) -> NotificationItem {
+        NotificationItem::new(
+            Some(id),
+            "app",
+            0,
+            "",
+            summary,
+            body,
+            actions.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            hints.end(),
+            -1,
+            glib::DateTime::now_local().ok(),
+        )
+    }
+
+    #[gtk::test]
+    fn rebinding_clears_previous_notification() {
+        adw::init().expect("Could not initialize libadwaita");
+        crate::load_resources();
+
+        let display = NotificationDisplay::new();
+        let imp = display.imp();
+
+        let hints = glib::VariantDict::new(None);
+        hints.insert_value("urgency", &2u8.to_variant());
+        hints.insert_value("value", &40i32.to_variant());
+        hints.insert_value("image-data", &(2, 2, 6, false, 8, 3, vec![255u8; 12]).to_variant());
+        let critical = item(1, "Critical", "<b>body</b>", &["default", "Open", "reply", "Reply"], hints);
+
+        display.set_item(Some(&critical));
+        assert!(display.has_css_class("critical"));
+        assert_eq!(imp.title_label.label(), "Critical");
+        assert!(imp.actions_box.first_child().is_some());
+        assert!(imp.progress_bar.is_visible());
+
+        // the image is decoded on a worker thread
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while imp.image.paintable().is_none() && Instant::now() < deadline {
+            glib::MainContext::default().iteration(false);
         }
+        assert!(imp.image.paintable().is_some());
+
+        let plain = item(2, "Plain", "text", &[], glib::VariantDict::new(None));
+        display.set_item(Some(&plain));
+        assert!(!display.has_css_class("critical"));
+        assert!(imp.actions_box.first_child().is_none());
+        assert!(!imp.actions_box.is_visible());
+        assert!(!imp.progress_bar.is_visible());
+        assert!(imp.image.paintable().is_none());
+        assert!(!imp.image.is_visible());
+        assert_eq!(imp.title_label.label(), "Plain");
+        assert_eq!(imp.body_label.label(), "text");
+
+        // the bindings to the first item are gone
+        critical.set_summary("Late update");
+        assert_eq!(imp.title_label.label(), "Plain");
+
+        display.set_item(None::<&NotificationItem>);
+        assert_eq!(imp.app_label.label(), "");
+        assert_eq!(imp.title_label.label(), "");
+        assert_eq!(imp.body_label.label(), "");
+        assert_eq!(imp.date_label.label(), "");
+        assert_eq!(imp.date_label.tooltip_text(), None);
+        assert_eq!(imp.close_button.action_target_value(), None);
+        assert!(imp.actions_box.first_child().is_none());
+        assert!(imp.image.paintable().is_none());
+        assert!(!imp.progress_bar.is_visible());
+
+        plain.set_summary("Late update");
+        assert_eq!(imp.title_label.label(), "");
+    }
+
+    #[gtk::test]
+    fn progress_only_animates_from_a_shown_value() {
+        adw::init().expect("Could not initialize libadwaita");
+        crate::load_resources();
+
+        let display = NotificationDisplay::new();
+        let imp = display.imp();
+        let progress = |value: Option<i32>| {
+            let hints = glib::VariantDict::new(None);
+            if let Some(value) = value {
+                hints.insert_value("value", &value.to_variant());
+            }
+            item(1, "Copying", "", &[], hints)
+        };
+
+        display.set_item(Some(&progress(Some(40))));
+        assert!(imp.progress_animation.borrow().is_none());
+
+        // an update of the same notification animates
+        display.set_item(Some(&progress(Some(60))));
+        assert!(imp.progress_animation.borrow().is_some());
+
+        // once the value went away the next one is shown as is
+        display.set_item(Some(&progress(None)));
+        assert!(!imp.progress_bar.is_visible());
+        display.set_item(Some(&progress(Some(80))));
+        assert!(imp.progress_animation.borrow().is_none());
+        assert_eq!(imp.progress_bar.fraction(), 0.8);
+
+        // as is a value of another notification bound in between
+        let other = item(2, "Other", "", &[], glib::VariantDict::new(None));
+        display.set_item(Some(&other));
+        display.set_item(Some(&progress(Some(90))));
+        assert!(imp.progress_animation.borrow().is_none());
+        assert_eq!(imp.progress_bar.fraction(), 0.9);
     }
 }
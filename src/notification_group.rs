@@ -0,0 +1,99 @@
+use adw::subclass::prelude::ObjectSubclassIsExt;
+use gtk::{
+    glib::{self, variant::ToVariant, Object},
+    prelude::{ActionableExt, ButtonExt, WidgetExt},
+    CompositeTemplate,
+};
+
+use crate::notification_server::NotificationItem;
+use crate::utils;
+
+mod inner {
+    use super::*;
+    use gtk::subclass::prelude::*;
+
+    #[derive(CompositeTemplate, Default)]
+    #[template(resource = "/shell/ui/notification-group.ui")]
+    pub struct NotificationGroupHeader {
+        #[template_child(id = "app-icon")]
+        pub app_icon: TemplateChild<gtk::Image>,
+
+        #[template_child(id = "app-label")]
+        pub app_label: TemplateChild<gtk::Label>,
+
+        #[template_child(id = "count-label")]
+        pub count_label: TemplateChild<gtk::Label>,
+
+        #[template_child(id = "expand-button")]
+        pub expand_button: TemplateChild<gtk::Button>,
+
+        #[template_child(id = "dismiss-button")]
+        pub dismiss_button: TemplateChild<gtk::Button>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for NotificationGroupHeader {
+        const NAME: &'static str = "NotificationGroupHeader";
+        type Type = super::NotificationGroupHeader;
+        type ParentType = gtk::Box;
+
+        fn new() -> Self {
+            Self {
+                ..Default::default()
+            }
+        }
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for NotificationGroupHeader {}
+    impl BoxImpl for NotificationGroupHeader {}
+    impl WidgetImpl for NotificationGroupHeader {}
+}
+
+glib::wrapper! {
+    /// Section header of one application's notifications.
+    pub struct NotificationGroupHeader(ObjectSubclass<inner::NotificationGroupHeader>)
+    @extends gtk::Widget, gtk::Box,
+    @implements gtk::Accessible, gtk::Buildable, gtk::ConstraintTarget, gtk::Orientable;
+}
+
+impl NotificationGroupHeader {
+    pub fn new() -> Self {
+        let obj = Object::new();
+        obj
+    }
+
+    /// Shows the group `key`, using `latest` for the application's name and icon.
+    pub fn set_group(&self, key: &str, latest: &NotificationItem, count: u32, expanded: bool) {
+        let imp = self.imp();
+        let target = key.to_variant();
+
+        let name = latest.display_name();
+        imp.app_icon
+            .set_from_gicon(&utils::first_usable_icon(self, latest.app_icon_candidates()));
+
+        imp.app_label.set_label(&name);
+        imp.count_label.set_label(&count.to_string());
+        imp.count_label.set_visible(count > 1);
+
+        imp.expand_button.set_action_target_value(Some(&target));
+        imp.expand_button.set_visible(count > 1);
+        imp.expand_button.set_icon_name(if expanded {
+            "pan-down-symbolic"
+        } else {
+            "pan-end-symbolic"
+        });
+        imp.expand_button.set_tooltip_text(Some(if expanded {
+            "Show latest only"
+        } else {
+            "Show all"
+        }));
+
+        imp.dismiss_button.set_action_target_value(Some(&target));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::notification_server::notification::NotificationItem;
+use crate::notification_server::server::{CloseReason, Server, ServerError};
+use gio::glib::object::{CastNone, ObjectExt};
+use gio::glib::variant::ToVariant;
+use gio::prelude::ListModelExt;
+use gtk::gio;
+use gtk::glib::{self, clone, closure_local};
+
+const CONTROL_DBUS_PATH: &str = "/io/github/johannes/shell/Notifications";
+const CONTROL_DBUS_INTERFACE: &str = "io.github.johannes.shell.Notifications";
+const CONTROL_INTROSPECTION_XML: &str = include_str!("control-introspect.xml");
+
+/// (id, app_name, app_icon, summary, body, actions, hints, expire_timeout,
+/// timestamp, updated, silent, restored)
+type Entry = (
+    u32,
+    String,
+    String,
+    String,
+    String,
+    Vec<String>,
+    glib::VariantDict,
+    i32,
+    i64,
+    i64,
+    bool,
+    bool,
+);
+
+/// Exports the shell's own interface, so other tools can inspect and drive the
+/// server whether or not it owns the freedesktop name.
+pub fn register(server: &Server, conn: &gio::DBusConnection) -> Result<(), ServerError> {
+    let node_info = gio::DBusNodeInfo::for_xml(CONTROL_INTROSPECTION_XML)?;
+    let interface_info = node_info
+        .interfaces()
+        .first()
+        .ok_or(ServerError::NoInterfaceInfo)?;
+
+    conn.register_object(CONTROL_DBUS_PATH, interface_info)
+        .method_call(clone!(
+            #[weak]
+            server,
+            move |_connection,
+                  _sender,
+                  _object_path,
+                  _interface_name,
+                  method_name,
+                  parameters,
+                  invocation| {
+                handle_method_call(&server, method_name, parameters, invocation);
+            }
+        ))
+        .build()?;
+
+    let store = server.get_store();
+    store.connect_items_changed(clone!(
+        #[weak]
+        conn,
+        move |store, _, _, _| {
+            emit(&conn, "CountChanged", &(store.n_items(),).to_variant());
+        }
+    ));
+    server.connect_closure(
+        "dnd-changed",
+        false,
+        closure_local!(
+            #[weak_allow_none]
+            conn,
+            move |_: Server, enabled: bool, missed: u32| {
+                if let Some(conn) = conn {
+                    emit(&conn, "DndChanged", &(enabled, missed).to_variant());
+                }
+            }
+        ),
+    );
+
+    Ok(())
+}
+
+fn handle_method_call(
+    server: &Server,
+    method_name: &str,
+    parameters: glib::Variant,
+    invocation: gio::DBusMethodInvocation,
+) {
+    match method_name {
+        "List" => {
+            let entries = entries(server, |item| !item.restored());
+            invocation.return_value(Some(&(entries,).to_variant()));
+        }
+        "GetHistory" => {
+            let entries = entries(server, |_| true);
+            invocation.return_value(Some(&(entries,).to_variant()));
+        }
+        "Dismiss" => {
+            let Some((id,)) = parameters.get::<(u32,)>() else {
+                return invalid_args(invocation);
+            };
+            let dismissed = server.close_notification(id, CloseReason::Dismissed);
+            invocation.return_value(Some(&(dismissed,).to_variant()));
+        }
+        "DismissAll" => {
+            server.clear_all();
+            invocation.return_value(None);
+        }
+        "InvokeAction" => {
+            let Some((id, key)) = parameters.get::<(u32, String)>() else {
+                return invalid_args(invocation);
+            };
+            let invoked = server.invoke_action(id, &key);
+            invocation.return_value(Some(&(invoked,).to_variant()));
+        }
+        "GetDnd" => {
+            invocation.return_value(Some(&(server.dnd(), server.missed_count()).to_variant()));
+        }
+        "SetDnd" => {
+            let Some((enabled,)) = parameters.get::<(bool,)>() else {
+                return invalid_args(invocation);
+            };
+            server.set_dnd(enabled);
+            invocation.return_value(None);
+        }
+        "GetRuleHits" => {
+            invocation.return_value(Some(&(server.rule_hits(),).to_variant()));
+        }
+        _ => {
+            invocation.return_error(
+                gio::DBusError::UnknownMethod,
+                &format!("Method {method_name} is not known to server"),
+            );
+        }
+    }
+}
+
+fn invalid_args(invocation: gio::DBusMethodInvocation) {
+    invocation.return_error(gio::DBusError::InvalidArgs, "Invalid arguments");
+}
+
+/// Stored notifications passing `filter`, newest first.
+fn entries(server: &Server, filter: impl Fn(&NotificationItem) -> bool) -> Vec<Entry> {
+    let store = server.get_store();
+    (0..store.n_items())
+        .filter_map(|i| store.item(i).and_downcast::<NotificationItem>())
+        .filter(|item| filter(item))
+        .map(|item| to_entry(&item))
+        .collect()
+}
+
+fn to_entry(item: &NotificationItem) -> Entry {
+    (
+        item.id(),
+        item.app_name(),
+        item.app_icon(),
+        item.summary(),
+        item.body(),
+        item.actions(),
+        item.hints_dict(),
+        item.expire_timeout(),
+        item.timestamp().map(|dt| dt.to_unix()).unwrap_or_default(),
+        item.updated().map(|dt| dt.to_unix()).unwrap_or_default(),
+        item.silent(),
+        item.restored(),
+    )
+}
+
+fn emit(conn: &gio::DBusConnection, signal: &str, args: &glib::Variant) {
+    if let Err(e) = conn.emit_signal(None, CONTROL_DBUS_PATH, CONTROL_DBUS_INTERFACE, signal, Some(args)) {
+        eprintln!("Error occured sending {signal} on the control interface: {e}");
+    }
+}
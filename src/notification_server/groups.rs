@@ -0,0 +1,306 @@
+use crate::notification_server::notification::NotificationItem;
+use adw::subclass::prelude::ObjectSubclassIsExt;
+use gio::glib::object::{Cast, CastNone, IsA};
+use gio::prelude::ListModelExt;
+use gtk::gio;
+use gtk::glib::{self, clone, Object};
+use std::collections::{HashMap, HashSet};
+
+mod inner {
+    use super::*;
+    use gio::subclass::prelude::ListModelImpl;
+    use glib::subclass::{object::ObjectImpl, types::ObjectSubclass};
+    use glib::types::StaticType;
+    use gtk::subclass::prelude::SectionModelImpl;
+    use std::cell::{OnceCell, RefCell};
+
+    pub struct Group {
+        pub key: String,
+        /// newest first, like the store
+        pub items: Vec<NotificationItem>,
+    }
+
+    #[derive(Default)]
+    pub struct NotificationGroups {
+        pub source: OnceCell<gio::ListModel>,
+        /// Groups ordered by their newest notification.
+        pub groups: RefCell<Vec<Group>>,
+        /// Keys of the groups showing all of their notifications, the others
+        /// only show the latest one.
+        pub expanded: RefCell<HashSet<String>>,
+    }
+
+    impl NotificationGroups {
+        pub fn visible_len(&self, group: &Group) -> u32 {
+            if self.expanded.borrow().contains(&group.key) {
+                group.items.len() as u32
+            } else {
+                group.items.len().min(1) as u32
+            }
+        }
+
+        /// Index of the group shown at `position`, together with the position
+        /// its section starts at.
+        pub fn locate(&self, position: u32) -> Option<(usize, u32)> {
+            let mut start = 0;
+            for (index, group) in self.groups.borrow().iter().enumerate() {
+                let len = self.visible_len(group);
+                if position < start + len {
+                    return Some((index, start));
+                }
+                start += len;
+            }
+            None
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for NotificationGroups {
+        const NAME: &'static str = "NotificationGroups";
+        type Type = super::NotificationGroups;
+        type ParentType = glib::Object;
+        type Interfaces = (gio::ListModel, gtk::SectionModel);
+    }
+
+    impl ObjectImpl for NotificationGroups {}
+
+    impl ListModelImpl for NotificationGroups {
+        fn item_type(&self) -> glib::Type {
+            NotificationItem::static_type()
+        }
+
+        fn n_items(&self) -> u32 {
+            self.groups
+                .borrow()
+                .iter()
+                .map(|group| self.visible_len(group))
+                .sum()
+        }
+
+        fn item(&self, position: u32) -> Option<glib::Object> {
+            let (index, start) = self.locate(position)?;
+            let groups = self.groups.borrow();
+            groups[index]
+                .items
+                .get((position - start) as usize)
+                .map(|item| item.clone().upcast())
+        }
+    }
+
+    impl SectionModelImpl for NotificationGroups {
+        fn section(&self, position: u32) -> (u32, u32) {
+            let Some((index, start)) = self.locate(position) else {
+                // past the end, the section covers everything after the last item
+                return (self.n_items(), u32::MAX);
+            };
+            let len = self.visible_len(&self.groups.borrow()[index]);
+            (start, start + len)
+        }
+    }
+}
+
+glib::wrapper! {
+    /// Sorts the notifications of a store into one section per application,
+    /// see `NotificationItem::group_key`.
+    pub struct NotificationGroups(ObjectSubclass<inner::NotificationGroups>)
+    @implements gio::ListModel, gtk::SectionModel;
+}
+
+impl NotificationGroups {
+    pub fn new(source: &impl IsA<gio::ListModel>) -> Self {
+        let obj: NotificationGroups = Object::new();
+        let source = source.clone().upcast::<gio::ListModel>();
+
+        source.connect_items_changed(clone!(
+            #[weak]
+            obj,
+            move |_, _, _, _| obj.rebuild()
+        ));
+        let _ = obj.imp().source.set(source);
+        obj.rebuild();
+        obj
+    }
+
+    fn rebuild(&self) {
+        let imp = self.imp();
+        let Some(source) = imp.source.get() else {
+            return;
+        };
+
+        // only notifications that weren't there before need their key worked out
+        let known: HashMap<NotificationItem, String> = imp
+            .groups
+            .borrow()
+            .iter()
+            .flat_map(|group| group.items.iter().map(|item| (item.clone(), group.key.clone())))
+            .collect();
+
+        let mut groups: Vec<inner::Group> = Vec::new();
+        for item in (0..source.n_items()).filter_map(|i| source.item(i).and_downcast::<NotificationItem>()) {
+            let key = known.get(&item).cloned().unwrap_or_else(|| item.group_key());
+            match groups.iter_mut().find(|group| group.key == key) {
+                Some(group) => group.items.push(item),
+                None => groups.push(inner::Group {
+                    key,
+                    items: vec![item],
+                }),
+            }
+        }
+
+        self.update_groups(groups);
+
+        // forget about groups that are gone, so they start collapsed when they
+        // return. Only now, removing them above needed their visible length.
+        let groups = imp.groups.borrow();
+        imp.expanded
+            .borrow_mut()
+            .retain(|key| groups.iter().any(|group| &group.key == key));
+    }
+
+    /// Turns the current groups into `groups` one group at a time, so only the
+    /// rows of groups that changed or moved are bound again.
+    fn update_groups(&self, groups: Vec<inner::Group>) {
+        let imp = self.imp();
+        let len = groups.len();
+
+        for (index, group) in groups.into_iter().enumerate() {
+            // groups before `index` already match, so a match can only come later
+            let current = imp.groups.borrow().iter().position(|g| g.key == group.key);
+            match current {
+                Some(current) if current == index => self.replace_group(index, group),
+                Some(current) => {
+                    self.remove_group(current);
+                    self.insert_group(index, group);
+                }
+                None => self.insert_group(index, group),
+            }
+        }
+
+        while imp.groups.borrow().len() > len {
+            self.remove_group(len);
+        }
+    }
+
+    fn section_start(&self, index: usize) -> u32 {
+        let imp = self.imp();
+        imp.groups.borrow()[..index]
+            .iter()
+            .map(|group| imp.visible_len(group))
+            .sum()
+    }
+
+    fn insert_group(&self, index: usize, group: inner::Group) {
+        let imp = self.imp();
+        let start = self.section_start(index);
+        let added = imp.visible_len(&group);
+        imp.groups.borrow_mut().insert(index, group);
+        self.items_changed(start, 0, added);
+    }
+
+    fn remove_group(&self, index: usize) {
+        let imp = self.imp();
+        let start = self.section_start(index);
+        let group = imp.groups.borrow_mut().remove(index);
+        self.items_changed(start, imp.visible_len(&group), 0);
+    }
+
+    /// Replaces a group that stays in place, announcing only the rows between
+    /// the ones both versions share at their start and end.
+    fn replace_group(&self, index: usize, group: inner::Group) {
+        let imp = self.imp();
+        let start = self.section_start(index);
+
+        let old = std::mem::replace(&mut imp.groups.borrow_mut()[index], group);
+        let (changed, count_changed) = {
+            let groups = imp.groups.borrow();
+            let new = &groups[index];
+            let old_rows = &old.items[..imp.visible_len(&old) as usize];
+            let new_rows = &new.items[..imp.visible_len(new) as usize];
+
+            let prefix = old_rows
+                .iter()
+                .zip(new_rows)
+                .take_while(|(a, b)| a == b)
+                .count();
+            let suffix = old_rows[prefix..]
+                .iter()
+                .rev()
+                .zip(new_rows[prefix..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            let removed = old_rows.len() - prefix - suffix;
+            let added = new_rows.len() - prefix - suffix;
+            let changed = (removed > 0 || added > 0).then_some((prefix as u32, removed as u32, added as u32));
+            (changed, old.items.len() != new.items.len())
+        };
+
+        if let Some((offset, removed, added)) = changed {
+            self.items_changed(start + offset, removed, added);
+        }
+
+        // the header shows the number of notifications and is bound with the
+        // first row, so that has to be announced when only hidden ones changed
+        let header_rebound = changed.is_some_and(|(offset, _, _)| offset == 0);
+        if count_changed && !header_rebound {
+            self.items_changed(start, 1, 1);
+        }
+    }
+
+    /// Number of notifications in the group, including the hidden ones.
+    pub fn group_len(&self, key: &str) -> u32 {
+        self.imp()
+            .groups
+            .borrow()
+            .iter()
+            .find(|group| group.key == key)
+            .map_or(0, |group| group.items.len() as u32)
+    }
+
+    fn visible_group_len(&self, key: &str) -> u32 {
+        let imp = self.imp();
+        imp.groups
+            .borrow()
+            .iter()
+            .find(|group| group.key == key)
+            .map_or(0, |group| imp.visible_len(group))
+    }
+
+    pub fn is_expanded(&self, key: &str) -> bool {
+        self.imp().expanded.borrow().contains(key)
+    }
+
+    pub fn set_expanded(&self, key: &str, expanded: bool) {
+        let imp = self.imp();
+        if self.is_expanded(key) == expanded {
+            return;
+        }
+
+        let section = {
+            let groups = imp.groups.borrow();
+            let mut start = 0;
+            let mut found = None;
+            for group in groups.iter() {
+                let len = imp.visible_len(group);
+                if group.key == key {
+                    found = Some((start, len));
+                    break;
+                }
+                start += len;
+            }
+            found
+        };
+
+        if expanded {
+            imp.expanded.borrow_mut().insert(key.to_string());
+        } else {
+            imp.expanded.borrow_mut().remove(key);
+        }
+
+        // replace the whole section, so its header is bound again with the new state
+        if let Some((start, removed)) = section {
+            let added = self.visible_group_len(key);
+            self.items_changed(start, removed, added);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::notification_server::notification::NotificationItem;
+use crate::notification_server::server::ServerConfig;
+use gio::glib::object::{Cast, CastNone, IsA};
+use gio::glib::variant::ToVariant;
+use gio::prelude::ListModelExt;
+use gtk::gio;
+use gtk::glib::{self, Variant};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+use thiserror::Error;
+
+const HISTORY_VERSION: u32 = 1;
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+
+/// One stored notification:
+/// (id, app_name, app_icon, summary, body, actions, hints, expire_timeout, unix timestamp)
+type Record = (
+    u32,
+    String,
+    String,
+    String,
+    String,
+    Vec<String>,
+    glib::VariantDict,
+    i32,
+    i64,
+);
+
+type HistoryFile = (u32, Vec<Record>);
+
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("history file has unsupported version {0}")]
+    Version(u32),
+    #[error("history file is malformed")]
+    Malformed,
+}
+
+/// Keeps the notification history on disk under `$XDG_STATE_HOME/shell`.
+#[derive(Clone)]
+pub struct History {
+    path: Rc<PathBuf>,
+    max_age: Duration,
+    max_count: usize,
+    pending: Rc<RefCell<Option<glib::SourceId>>>,
+}
+
+impl History {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            path: Rc::new(glib::user_state_dir().join("shell").join("history.gvariant")),
+            max_age: config.history_max_age,
+            max_count: config.history_max_count,
+            pending: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.max_count > 0
+    }
+
+    /// Reads the stored notifications, newest first, dropping everything outside
+    /// the retention limits.
+    pub fn load(&self) -> Result<Vec<NotificationItem>, HistoryError> {
+        if !self.enabled() || !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let data = std::fs::read(self.path.as_ref())?;
+        let variant = Variant::from_data::<HistoryFile, _>(data);
+        let (version, records) = variant
+            .get::<HistoryFile>()
+            .ok_or(HistoryError::Malformed)?;
+
+        if version != HISTORY_VERSION {
+            return Err(HistoryError::Version(version));
+        }
+
+        let items = records
+            .into_iter()
+            .map(from_record)
+            .filter(|item| self.retained(item))
+            .take(self.max_count)
+            .collect();
+
+        Ok(items)
+    }
+
+    fn retained(&self, item: &NotificationItem) -> bool {
+        let Some(timestamp) = item.timestamp() else {
+            return false;
+        };
+        let Ok(now) = glib::DateTime::now_local() else {
+            return true;
+        };
+        let age = now.difference(&timestamp).as_seconds();
+        age <= self.max_age.as_secs() as i64
+    }
+
+    pub fn save(&self, store: &impl IsA<gio::ListModel>) -> Result<(), HistoryError> {
+        if !self.enabled() {
+            return Ok(());
+        }
+
+        let records: Vec<Record> = (0..store.n_items())
+            .filter_map(|i| store.item(i).and_downcast::<NotificationItem>())
+            .filter(|item| !item.skip_history() && !item.get_hints().transient.unwrap_or(false))
+            .filter(|item| self.retained(item))
+            .take(self.max_count)
+            .map(|item| to_record(&item))
+            .collect();
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let variant = (HISTORY_VERSION, records).to_variant();
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, variant.data_as_bytes())?;
+        std::fs::rename(&tmp, self.path.as_ref())?;
+        Ok(())
+    }
+
+    /// Saves the store a short while after the last change, so bursts of
+    /// notifications only cause a single write.
+    pub fn schedule_save(&self, store: &impl IsA<gio::ListModel>) {
+        if !self.enabled() {
+            return;
+        }
+
+        if let Some(source) = self.pending.borrow_mut().take() {
+            source.remove();
+        }
+
+        let h = self.clone();
+        let store = store.clone().upcast::<gio::ListModel>();
+        let source = glib::timeout_add_local_once(SAVE_DELAY, move || {
+            h.pending.borrow_mut().take();
+            if let Err(e) = h.save(&store) {
+                eprintln!("Could not save notification history: {e}");
+            }
+        });
+        *self.pending.borrow_mut() = Some(source);
+    }
+}
+
+fn to_record(item: &NotificationItem) -> Record {
+    (
+        item.id(),
+        item.app_name(),
+        item.app_icon(),
+        item.summary(),
+        item.body(),
+        item.actions(),
+        item.hints_dict(),
+        item.expire_timeout(),
+        item.timestamp().map(|dt| dt.to_unix()).unwrap_or_default(),
+    )
+}
+
+fn from_record(record: Record) -> NotificationItem {
+    let (id, app_name, app_icon, summary, body, actions, hints, expire_timeout, timestamp) = record;
+    let item = NotificationItem::new(
+        Some(id),
+        app_name,
+        0,
+        app_icon,
+        summary,
+        body,
+        actions,
+        hints.end(),
+        expire_timeout,
+        glib::DateTime::from_unix_local(timestamp).ok(),
+    );
+    item.set_restored(true);
+    item
+}
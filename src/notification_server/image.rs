@@ -0,0 +1,408 @@
+use crate::notification_server::notification::{NotificationImageData, NotificationItem};
+use gdk_pixbuf::Pixbuf;
+use gio::glib::variant::ToVariant;
+use gtk::glib;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Hints carrying raw pixels, in the order they are looked at.
+const IMAGE_DATA_HINTS: [&str; 2] = ["image-data", "icon-data"];
+
+#[derive(Clone, Debug)]
+pub struct ImageLimits {
+    /// Largest width and height accepted from a client.
+    pub max_width: i32,
+    pub max_height: i32,
+    /// Largest pixel buffer accepted from a client.
+    pub max_bytes: usize,
+    /// Images are scaled down to fit this size before they are stored.
+    pub store_size: i32,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        Self {
+            max_width: 4096,
+            max_height: 4096,
+            max_bytes: 64 * 1024 * 1024,
+            store_size: 256,
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ImageError {
+    #[error("image has no pixels ({0}x{1})")]
+    Empty(i32, i32),
+    #[error("image of {0}x{1} exceeds the maximum size")]
+    TooLarge(i32, i32),
+    #[error("image data of {0} bytes exceeds the maximum size")]
+    TooManyBytes(usize),
+    #[error("unsupported bits per sample {0}")]
+    BitsPerSample(i32),
+    #[error("{channels} channels do not match has_alpha={has_alpha}")]
+    Channels { channels: i32, has_alpha: bool },
+    #[error("rowstride {rowstride} is shorter than a row of {row} bytes")]
+    Rowstride { rowstride: i32, row: usize },
+    #[error("image needs {needed} bytes but only {len} were sent")]
+    Truncated { needed: usize, len: usize },
+    #[error("could not load {0}")]
+    Unreadable(PathBuf),
+}
+
+/// Checks that the client's description of the pixels is consistent and within
+/// `limits`, so the buffer can be handed to gdk-pixbuf safely.
+pub fn validate(image: &NotificationImageData, limits: &ImageLimits) -> Result<(), ImageError> {
+    let (width, height, rowstride, has_alpha, bits_per_sample, channels, data) = image;
+    let (width, height, rowstride) = (*width, *height, *rowstride);
+
+    if width <= 0 || height <= 0 {
+        return Err(ImageError::Empty(width, height));
+    }
+    if width > limits.max_width || height > limits.max_height {
+        return Err(ImageError::TooLarge(width, height));
+    }
+    if data.len() > limits.max_bytes {
+        return Err(ImageError::TooManyBytes(data.len()));
+    }
+    // gdk-pixbuf only handles 8 bit RGB and RGBA
+    if *bits_per_sample != 8 {
+        return Err(ImageError::BitsPerSample(*bits_per_sample));
+    }
+    if *channels != if *has_alpha { 4 } else { 3 } {
+        return Err(ImageError::Channels {
+            channels: *channels,
+            has_alpha: *has_alpha,
+        });
+    }
+
+    let row = (width as usize)
+        .checked_mul(*channels as usize)
+        .ok_or(ImageError::TooLarge(width, height))?;
+    if rowstride < 0 || (rowstride as usize) < row {
+        return Err(ImageError::Rowstride { rowstride, row });
+    }
+
+    // the last row does not need to be padded up to the rowstride
+    let needed = (rowstride as usize)
+        .checked_mul(height as usize - 1)
+        .and_then(|n| n.checked_add(row))
+        .ok_or(ImageError::TooLarge(width, height))?;
+    if data.len() < needed {
+        return Err(ImageError::Truncated {
+            needed,
+            len: data.len(),
+        });
+    }
+
+    Ok(())
+}
+
+pub fn pixbuf_from_image_data(
+    image: NotificationImageData,
+    limits: &ImageLimits,
+) -> Result<Pixbuf, ImageError> {
+    validate(&image, limits)?;
+
+    let (width, height, rowstride, has_alpha, bits_per_sample, _, data) = image;
+    Ok(Pixbuf::from_bytes(
+        &glib::Bytes::from_owned(data),
+        gdk_pixbuf::Colorspace::Rgb,
+        has_alpha,
+        bits_per_sample,
+        width,
+        height,
+        rowstride,
+    ))
+}
+
+/// Loads an image file, checking its size against `limits` before anything is
+/// decoded. Larger images are decoded at the scale that brings their shorter
+/// side down to `size`.
+pub fn pixbuf_from_file(path: &Path, size: i32, limits: &ImageLimits) -> Result<Pixbuf, ImageError> {
+    // reading a fifo or a device could block the decoder forever
+    if !path.metadata().is_ok_and(|meta| meta.is_file()) {
+        return Err(ImageError::Unreadable(path.to_path_buf()));
+    }
+    let (_, width, height) =
+        Pixbuf::file_info(path).ok_or_else(|| ImageError::Unreadable(path.to_path_buf()))?;
+    if width <= 0 || height <= 0 {
+        return Err(ImageError::Empty(width, height));
+    }
+    if width > limits.max_width || height > limits.max_height {
+        return Err(ImageError::TooLarge(width, height));
+    }
+    // some loaders decode the whole image before scaling it
+    let bytes = width as usize * height as usize * 4;
+    if bytes > limits.max_bytes {
+        return Err(ImageError::TooManyBytes(bytes));
+    }
+
+    let scale = (size as f64 / width.min(height) as f64).min(1.0);
+    let (w, h) = (
+        ((width as f64 * scale).round() as i32).max(1),
+        ((height as f64 * scale).round() as i32).max(1),
+    );
+    Pixbuf::from_file_at_scale(path, w, h, false).map_err(|_| ImageError::Unreadable(path.to_path_buf()))
+}
+
+pub fn image_data_from_pixbuf(pixbuf: &Pixbuf) -> NotificationImageData {
+    (
+        pixbuf.width(),
+        pixbuf.height(),
+        pixbuf.rowstride(),
+        pixbuf.has_alpha(),
+        pixbuf.bits_per_sample(),
+        pixbuf.n_channels(),
+        pixbuf.read_pixel_bytes().to_vec(),
+    )
+}
+
+/// Scales `pixbuf` down so neither side exceeds `size`, keeping the aspect ratio.
+pub fn downscale(pixbuf: &Pixbuf, size: i32) -> Pixbuf {
+    let (width, height) = (pixbuf.width(), pixbuf.height());
+    if width <= size && height <= size {
+        return pixbuf.clone();
+    }
+
+    let scale = size as f64 / width.max(height) as f64;
+    let (w, h) = (
+        ((width as f64 * scale).round() as i32).max(1),
+        ((height as f64 * scale).round() as i32).max(1),
+    );
+    pixbuf
+        .scale_simple(w, h, gdk_pixbuf::InterpType::Bilinear)
+        .unwrap_or_else(|| pixbuf.clone())
+}
+
+/// Drops image hints that fail validation, logging why, and replaces the others
+/// with a copy scaled down to `limits.store_size`. An image-path is left alone,
+/// the file is only read when the image is decoded off the main thread.
+pub fn sanitize_hints(item: &NotificationItem, limits: &ImageLimits) {
+    let hints = item.hints_dict();
+    let mut changed = false;
+
+    for key in IMAGE_DATA_HINTS {
+        let Some(value) = hints.lookup_value(key, None) else {
+            continue;
+        };
+
+        let image = value
+            .get::<NotificationImageData>()
+            .ok_or_else(|| format!("{key} has type {} instead of (iiibiiay)", value.type_()))
+            .and_then(|image| pixbuf_from_image_data(image, limits).map_err(|e| e.to_string()));
+
+        changed = true;
+        match image {
+            Ok(pixbuf) => {
+                let scaled = downscale(&pixbuf, limits.store_size);
+                hints.insert_value(key, &image_data_from_pixbuf(&scaled).to_variant());
+            }
+            Err(e) => {
+                eprintln!("Rejected {key} of notification from {}: {e}", item.app_name());
+                hints.remove(key);
+            }
+        }
+    }
+
+    if changed {
+        item.set_hints(hints.end());
+    }
+}
+
+/// Where the picture of a notification comes from. Everything in here can be
+/// sent to a worker thread, unlike a `Pixbuf`.
+#[derive(Clone, Debug, Hash)]
+pub enum ImageSource {
+    Data(NotificationImageData),
+    File(PathBuf),
+}
+
+/// Decoded pixels, ready to be turned into a texture on the main thread.
+#[derive(Debug)]
+pub struct DecodedImage {
+    pub width: i32,
+    pub height: i32,
+    pub stride: usize,
+    pub has_alpha: bool,
+    pub data: Vec<u8>,
+}
+
+impl ImageSource {
+    /// Decodes the image within `limits`, cropped to a centered square of at
+    /// most `size` pixels. This blocks, so it belongs on a worker thread.
+    pub fn decode(self, size: i32, limits: &ImageLimits) -> Option<DecodedImage> {
+        let pixbuf = match self {
+            Self::Data(data) => pixbuf_from_image_data(data, limits)
+                .map_err(|e| eprintln!("Ignoring invalid image data: {e}"))
+                .ok()?,
+            Self::File(path) => pixbuf_from_file(&path, size, limits)
+                .map_err(|e| eprintln!("Ignoring image {}: {e}", path.display()))
+                .ok()?,
+        };
+
+        let scaled = downscale(&crop_square(&pixbuf), size);
+        Some(DecodedImage {
+            width: scaled.width(),
+            height: scaled.height(),
+            stride: scaled.rowstride() as usize,
+            has_alpha: scaled.has_alpha(),
+            data: scaled.read_pixel_bytes().to_vec(),
+        })
+    }
+}
+
+fn crop_square(pixbuf: &Pixbuf) -> Pixbuf {
+    let (w, h) = (pixbuf.width(), pixbuf.height());
+    let s = w.min(h);
+    pixbuf.new_subpixbuf((w - s) / 2, (h - s) / 2, s, s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An RGB image of `width`x`height` with `rowstride` and `len` bytes of data.
+    fn rgb(width: i32, height: i32, rowstride: i32, len: usize) -> NotificationImageData {
+        (width, height, rowstride, false, 8, 3, vec![0; len])
+    }
+
+    /// Writes a black `width`x`height` PNG called `name` into `dir`.
+    fn png(dir: &Path, name: &str, width: i32, height: i32) -> PathBuf {
+        let pixbuf = Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, false, 8, width, height).unwrap();
+        pixbuf.fill(0);
+        let path = dir.join(name);
+        pixbuf.savev(&path, "png", &[]).unwrap();
+        path
+    }
+
+    #[test]
+    fn validate_rejects_bad_images() {
+        let limits = ImageLimits {
+            max_width: 64,
+            max_height: 64,
+            max_bytes: 64 * 64 * 4,
+            store_size: 16,
+        };
+
+        let cases: [(&str, NotificationImageData, ImageError); 11] = [
+            ("zero width", rgb(0, 4, 12, 48), ImageError::Empty(0, 4)),
+            ("zero height", rgb(4, 0, 12, 48), ImageError::Empty(4, 0)),
+            ("negative size", rgb(-4, -4, 12, 48), ImageError::Empty(-4, -4)),
+            ("too wide", rgb(65, 1, 195, 195), ImageError::TooLarge(65, 1)),
+            ("too high", rgb(1, 65, 3, 195), ImageError::TooLarge(1, 65)),
+            ("too many bytes", rgb(4, 4, 12, 16385), ImageError::TooManyBytes(16385)),
+            (
+                "16 bits per sample",
+                (4, 4, 24, false, 16, 3, vec![0; 96]),
+                ImageError::BitsPerSample(16),
+            ),
+            (
+                "alpha with 3 channels",
+                (4, 4, 12, true, 8, 3, vec![0; 48]),
+                ImageError::Channels { channels: 3, has_alpha: true },
+            ),
+            (
+                "no alpha with 4 channels",
+                (4, 4, 16, false, 8, 4, vec![0; 64]),
+                ImageError::Channels { channels: 4, has_alpha: false },
+            ),
+            ("short rowstride", rgb(4, 4, 11, 48), ImageError::Rowstride { rowstride: 11, row: 12 }),
+            // rowstride * (height - 1) + row = 16 * 3 + 12
+            ("truncated", rgb(4, 4, 16, 59), ImageError::Truncated { needed: 60, len: 59 }),
+        ];
+
+        for (name, image, error) in cases {
+            assert_eq!(validate(&image, &limits), Err(error), "{name}");
+        }
+    }
+
+    #[test]
+    fn validate_accepts_unpadded_last_row() {
+        let limits = ImageLimits::default();
+
+        assert_eq!(validate(&rgb(4, 4, 16, 60), &limits), Ok(()));
+        assert_eq!(validate(&rgb(4, 4, 16, 64), &limits), Ok(()));
+        assert_eq!(validate(&rgb(4, 4, 12, 48), &limits), Ok(()));
+        assert_eq!(validate(&(1, 1, 4, true, 8, 4, vec![0; 4]), &limits), Ok(()));
+    }
+
+    #[test]
+    fn decode_respects_configured_limits() {
+        let dir = glib::mkdtemp(glib::tmp_dir().join("shell-image-XXXXXX")).unwrap();
+        let limits = ImageLimits {
+            max_width: 64,
+            max_height: 64,
+            max_bytes: 32 * 32 * 4,
+            store_size: 16,
+        };
+
+        let wide = png(&dir, "wide.png", 65, 1);
+        assert_eq!(pixbuf_from_file(&wide, 16, &limits).err(), Some(ImageError::TooLarge(65, 1)));
+        assert!(ImageSource::File(wide).decode(16, &limits).is_none());
+
+        let big = png(&dir, "big.png", 33, 32);
+        assert_eq!(
+            pixbuf_from_file(&big, 16, &limits).err(),
+            Some(ImageError::TooManyBytes(33 * 32 * 4))
+        );
+        assert!(ImageSource::File(big.clone()).decode(16, &limits).is_none());
+        assert!(ImageSource::File(big).decode(16, &ImageLimits::default()).is_some());
+
+        // not a regular file, so never opened
+        assert_eq!(
+            pixbuf_from_file(&dir, 16, &limits).err(),
+            Some(ImageError::Unreadable(dir.clone()))
+        );
+
+        let data = rgb(65, 1, 195, 195);
+        assert!(ImageSource::Data(data.clone()).decode(16, &limits).is_none());
+        assert!(ImageSource::Data(data).decode(16, &ImageLimits::default()).is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_decoded_at_display_size() {
+        let dir = glib::mkdtemp(glib::tmp_dir().join("shell-image-XXXXXX")).unwrap();
+        let path = png(&dir, "image.png", 64, 32);
+        let limits = ImageLimits::default();
+
+        let pixbuf = pixbuf_from_file(&path, 16, &limits).unwrap();
+        assert_eq!((pixbuf.width(), pixbuf.height()), (32, 16));
+
+        let image = ImageSource::File(path).decode(16, &limits).unwrap();
+        assert_eq!((image.width, image.height), (16, 16));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn app_icon_paths_respect_configured_limits() {
+        let dir = glib::mkdtemp(glib::tmp_dir().join("shell-image-XXXXXX")).unwrap();
+        let limits = ImageLimits {
+            max_width: 64,
+            max_height: 64,
+            max_bytes: 64 * 64 * 4,
+            store_size: 16,
+        };
+        let item = |app_icon: String| {
+            let hints = glib::VariantDict::new(None).end();
+            NotificationItem::new(Some(1), "app", 0, app_icon, "summary", "", Vec::new(), hints, -1, None)
+        };
+
+        let icon = png(&dir, "icon.png", 48, 48);
+        let uri = glib::filename_to_uri(&icon, None).unwrap().to_string();
+        for app_icon in [icon.display().to_string(), uri] {
+            let source = item(app_icon).image_source();
+            assert!(matches!(&source, Some(ImageSource::File(path)) if *path == icon));
+            assert!(source.unwrap().decode(16, &limits).is_some());
+        }
+
+        let huge = png(&dir, "huge.png", 65, 65);
+        let source = item(huge.display().to_string()).image_source().unwrap();
+        assert!(source.decode(16, &limits).is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+use gtk::glib;
+
+/// Converts a notification body using the spec's markup subset into markup a
+/// `gtk::Label` can display. Supported are `<b>`, `<i>`, `<u>`, `<a href>` and
+/// `<img alt>`, any other tag is shown as text. Bodies with unbalanced tags are
+/// shown as plain text. With `linkify` bare http(s) URLs become links as well.
+pub fn body_to_pango(body: &str, linkify: bool) -> String {
+    convert(body, linkify).unwrap_or_else(|| {
+        let mut out = String::with_capacity(body.len());
+        push_plain(&mut out, body, linkify);
+        out
+    })
+}
+
+fn convert(body: &str, linkify: bool) -> Option<String> {
+    let mut out = String::with_capacity(body.len());
+    let mut open: Vec<&'static str> = Vec::new();
+    let mut rest = body;
+
+    while let Some(start) = rest.find('<') {
+        push_text_linkified(&mut out, &rest[..start], linkify && !open.contains(&"a"));
+        rest = &rest[start..];
+
+        let end = rest.find('>')?;
+        let tag = &rest[1..end];
+        let raw = &rest[..=end];
+        rest = &rest[end + 1..];
+
+        match Tag::parse(tag) {
+            Some(Tag::Open(name)) => {
+                out.push('<');
+                out.push_str(name);
+                out.push('>');
+                open.push(name);
+            }
+            Some(Tag::Close(name)) => {
+                if open.pop() != Some(name) {
+                    return None;
+                }
+                out.push_str("</");
+                out.push_str(name);
+                out.push('>');
+            }
+            Some(Tag::Link(href)) => {
+                out.push_str("<a href=\"");
+                out.push_str(&glib::markup_escape_text(&href));
+                out.push_str("\">");
+                open.push("a");
+            }
+            Some(Tag::Image(alt)) => {
+                if let Some(alt) = alt {
+                    out.push_str(&glib::markup_escape_text(&alt));
+                }
+            }
+            Some(Tag::LineBreak) => out.push('\n'),
+            None => {
+                out.push_str("&lt;");
+                push_text(&mut out, &raw[1..]);
+            }
+        }
+    }
+    push_text_linkified(&mut out, rest, linkify);
+
+    open.is_empty().then_some(out)
+}
+
+/// Escapes `text` completely, only turning bare URLs into links.
+fn push_plain(out: &mut String, text: &str, linkify: bool) {
+    let mut rest = text;
+    while linkify {
+        let Some((start, end)) = find_url(rest) else {
+            break;
+        };
+        out.push_str(&glib::markup_escape_text(&rest[..start]));
+        push_link(out, &rest[start..end], &rest[start..end]);
+        rest = &rest[end..];
+    }
+    out.push_str(&glib::markup_escape_text(rest));
+}
+
+fn push_text_linkified(out: &mut String, text: &str, linkify: bool) {
+    let mut rest = text;
+    while linkify {
+        let Some((start, end)) = find_url(rest) else {
+            break;
+        };
+        push_text(out, &rest[..start]);
+        let url = &rest[start..end];
+        push_link(out, &unescape(url), url);
+        rest = &rest[end..];
+    }
+    push_text(out, rest);
+}
+
+fn push_link(out: &mut String, href: &str, text: &str) {
+    out.push_str("<a href=\"");
+    out.push_str(&glib::markup_escape_text(href));
+    out.push_str("\">");
+    out.push_str(&glib::markup_escape_text(&unescape(text)));
+    out.push_str("</a>");
+}
+
+/// Byte range of the first http(s) URL in `text`, without trailing punctuation.
+fn find_url(text: &str) -> Option<(usize, usize)> {
+    let start = ["https://", "http://"]
+        .iter()
+        .filter_map(|scheme| text.find(scheme))
+        .min()?;
+
+    let len = text[start..]
+        .find(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+        .unwrap_or(text.len() - start);
+    let url = text[start..start + len].trim_end_matches(['.', ',', ';', ':', '!', '?', ')']);
+
+    Some((start, start + url.len()))
+}
+
+enum Tag {
+    Open(&'static str),
+    Close(&'static str),
+    Link(String),
+    Image(Option<String>),
+    LineBreak,
+}
+
+impl Tag {
+    fn parse(tag: &str) -> Option<Self> {
+        let tag = tag.trim();
+        let (closing, tag) = match tag.strip_prefix('/') {
+            Some(tag) => (true, tag.trim_start()),
+            None => (false, tag),
+        };
+        let tag = tag.strip_suffix('/').unwrap_or(tag).trim_end();
+
+        let name_end = tag
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        let attrs = &tag[name_end..];
+
+        let simple = match name.as_str() {
+            "b" => Some("b"),
+            "i" => Some("i"),
+            "u" => Some("u"),
+            "a" => Some("a"),
+            _ => None,
+        };
+
+        match (name.as_str(), closing) {
+            ("a", false) => Some(Tag::Link(attribute(attrs, "href")?)),
+            ("img", false) => Some(Tag::Image(attribute(attrs, "alt"))),
+            ("img", true) => Some(Tag::Image(None)),
+            ("br", _) => Some(Tag::LineBreak),
+            (_, false) if attrs.trim().is_empty() => simple.map(Tag::Open),
+            (_, true) => simple.map(Tag::Close),
+            _ => None,
+        }
+    }
+}
+
+/// Value of `name="..."` or `name='...'` within the attribute part of a tag.
+fn attribute(attrs: &str, name: &str) -> Option<String> {
+    let mut rest = attrs;
+    loop {
+        let pos = rest.find(name)?;
+        let before = rest[..pos].chars().last();
+        rest = &rest[pos + name.len()..];
+
+        if before.is_some_and(|c| !c.is_whitespace()) {
+            continue;
+        }
+        let Some(value) = rest.trim_start().strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = &value[1..];
+        let end = value.find(quote)?;
+        return Some(unescape(&value[..end]));
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Escapes text between tags, keeping entities the client already escaped.
+fn push_text(out: &mut String, text: &str) {
+    let mut rest = text;
+    while let Some(pos) = rest.find(['&', '<', '>']) {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if rest.starts_with('<') {
+            out.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        }
+        if rest.starts_with('>') {
+            out.push_str("&gt;");
+            rest = &rest[1..];
+            continue;
+        }
+
+        match entity_len(rest) {
+            Some(len) => {
+                out.push_str(&rest[..len]);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push_str("&amp;");
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+/// Length of a well formed entity like `&amp;` or `&#39;` at the start of `text`.
+fn entity_len(text: &str) -> Option<usize> {
+    let end = text.find(';')?;
+    let name = &text[1..end];
+
+    let valid = match name.strip_prefix('#') {
+        Some(num) => {
+            let code = match num.strip_prefix(['x', 'X']) {
+                Some(hex) => parse_code(hex, 16),
+                None => parse_code(num, 10),
+            };
+            code.is_some_and(is_markup_char)
+        }
+        None => matches!(name, "amp" | "lt" | "gt" | "quot" | "apos"),
+    };
+
+    valid.then_some(end + 1)
+}
+
+fn parse_code(digits: &str, radix: u32) -> Option<u32> {
+    // from_str_radix also takes a leading sign
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    u32::from_str_radix(digits, radix).ok()
+}
+
+/// Whether GMarkup accepts a character reference to `code`: any char but NUL,
+/// U+FFFE and U+FFFF.
+fn is_markup_char(code: u32) -> bool {
+    char::from_u32(code).is_some_and(|c| !matches!(c, '\0' | '\u{fffe}' | '\u{ffff}'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_tags_are_kept() {
+        assert_eq!(
+            body_to_pango("<b>bold</b> <I>italic</I> <u>under</u>", false),
+            "<b>bold</b> <i>italic</i> <u>under</u>"
+        );
+        assert_eq!(body_to_pango("one<br/>two", false), "one\ntwo");
+        assert_eq!(
+            body_to_pango("<img src=\"x.png\" alt=\"a &amp; b\"/>", false),
+            "a &amp; b"
+        );
+        assert_eq!(
+            body_to_pango("<a href=\"https://example.org/?a=1&amp;b=2\">link</a>", false),
+            "<a href=\"https://example.org/?a=1&amp;b=2\">link</a>"
+        );
+    }
+
+    #[test]
+    fn unknown_tags_are_shown_as_text() {
+        assert_eq!(body_to_pango("<span>x</span>", false), "&lt;span&gt;x&lt;/span&gt;");
+        assert_eq!(body_to_pango("a << b > c", false), "a &lt;&lt; b &gt; c");
+        assert_eq!(body_to_pango("<<b>x</b>", false), "&lt;&lt;b&gt;x&lt;/b&gt;");
+    }
+
+    #[test]
+    fn unbalanced_markup_falls_back_to_plain_text() {
+        assert_eq!(body_to_pango("<b>open", false), "&lt;b&gt;open");
+        assert_eq!(
+            body_to_pango("<b><i>x</b></i>", false),
+            "&lt;b&gt;&lt;i&gt;x&lt;/b&gt;&lt;/i&gt;"
+        );
+        assert_eq!(body_to_pango("a < b", false), "a &lt; b");
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(body_to_pango("&amp; &lt; &#39; &#x27;", false), "&amp; &lt; &#39; &#x27;");
+        assert_eq!(body_to_pango("AT&T & co", false), "AT&amp;T &amp; co");
+        assert_eq!(body_to_pango("&nbsp;", false), "&amp;nbsp;");
+    }
+
+    #[test]
+    fn invalid_character_references_are_escaped() {
+        let references = [
+            "&#0;", "&#x0;", "&#99999999;", "&#x110000;", "&#xd800;", "&#xFFFE;", "&#+1;", "&#;", "&#x;",
+        ];
+        for reference in references {
+            assert_eq!(
+                body_to_pango(reference, false),
+                format!("&amp;{}", &reference[1..]),
+                "{reference}"
+            );
+        }
+    }
+
+    #[test]
+    fn bare_urls_are_linked() {
+        assert_eq!(
+            body_to_pango("see https://example.org/a?b=1&amp;c=2.", true),
+            "see <a href=\"https://example.org/a?b=1&amp;c=2\">https://example.org/a?b=1&amp;c=2</a>."
+        );
+        assert_eq!(
+            body_to_pango("<a href=\"https://example.org\">https://example.org</a>", true),
+            "<a href=\"https://example.org\">https://example.org</a>"
+        );
+        assert_eq!(body_to_pango("https://example.org", false), "https://example.org");
+    }
+
+    #[test]
+    fn output_is_valid_markup() {
+        // without links, which gtk::Label takes out before pango sees the markup
+        let bodies = [
+            "a << b > c",
+            "<b>x</b> & <y>",
+            "&#0; &#99999999; &#65;",
+            "<b>unbalanced",
+            "<i>\"q\"</i> <img alt='<z>'/>",
+        ];
+        for body in bodies {
+            let markup = body_to_pango(body, false);
+            assert!(gtk::pango::parse_markup(&markup, '\0').is_ok(), "{body} -> {markup}");
+        }
+    }
+}
@@ -1,6 +1,17 @@
+mod control;
+mod groups;
+mod history;
+mod image;
+mod markup;
 mod notification;
+mod rules;
 mod server;
 mod store;
+pub use groups::NotificationGroups;
+pub use image::{DecodedImage, ImageLimits};
+pub use markup::body_to_pango;
 pub use notification::NotificationItem;
 pub use notification::Urgency;
 pub use server::Server as NotificationServer;
+pub use server::ServerConfig;
+pub use store::IDStore;
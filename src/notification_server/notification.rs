@@ -1,19 +1,27 @@
 
-use gdk_pixbuf::Pixbuf;
 use gio::{
     glib::{
         property::PropertyGet,
         variant::StaticVariantType,
         UriFlags,
     },
-    prelude::FileExt,
+    prelude::{AppInfoExt, Cast, FileExt},
 };
 use gtk::glib::{self, Object};
+use gtk::gdk;
+
+use crate::image_cache::IMAGE_SIZE;
+use crate::notification_server::image::ImageSource;
+use adw::subclass::prelude::ObjectSubclassIsExt;
+use std::cell::Cell;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
 
 mod inner {
 
     use glib::prelude::ObjectExt;
     use glib::subclass::object::{DerivedObjectProperties, ObjectImpl, ObjectImplExt};
+    use glib::subclass::types::ObjectSubclassExt;
     use glib::{self, Properties};
     use std::cell::RefCell;
 
@@ -42,14 +50,37 @@ mod inner {
         #[property(get, set)]
         pub actions: RefCell<Vec<String>>,
 
-        #[property(get, set)]
-        pub hints: RefCell<glib::VariantDict>,
+        /// The `a{sv}` hints as sent by the client. Only ever replaced as a
+        /// whole, so reading them never notifies.
+        #[property(get, set, builder(glib::VariantTy::VARDICT))]
+        pub hints: RefCell<Option<glib::Variant>>,
 
         #[property(get, set, minimum = i32::MIN, maximum = i32::MAX, default = -1i32)]
         pub expire_timeout: RefCell<i32>,
 
+        /// When the notification was first seen, kept across replacements.
         #[property(get, set)]
         pub timestamp: RefCell<Option<glib::DateTime>>,
+
+        /// When the notification was last replaced through replaces_id.
+        #[property(get, set)]
+        pub updated: RefCell<Option<glib::DateTime>>,
+
+        /// Set when the notification arrived during Do Not Disturb and should
+        /// not pop up or be styled for attention.
+        #[property(get, set)]
+        pub silent: RefCell<bool>,
+
+        /// Set for notifications that were loaded from the history of a previous session.
+        #[property(get, set)]
+        pub restored: RefCell<bool>,
+
+        /// Set by a rule for notifications that are left out of the history.
+        #[property(get, set)]
+        pub skip_history: RefCell<bool>,
+
+        /// Memoized `image_hash`, `Some(None)` meaning there is no image.
+        pub image_hash: Cell<Option<Option<u64>>>,
     }
 
     #[glib::object_subclass]
@@ -68,6 +99,8 @@ mod inner {
     impl ObjectImpl for NotificationItem {
         fn constructed(&self) {
             self.parent_constructed();
+
+            self.obj().connect_hints_notify(|item| item.imp().image_hash.set(None));
         }
     }
 }
@@ -85,7 +118,7 @@ impl NotificationItem {
         summary: impl Into<String>,
         body: impl Into<String>,
         actions: impl Into<Vec<String>>,
-        hints: impl Into<glib::VariantDict>,
+        hints: glib::Variant,
         expire_timeout: i32,
         timestamp: Option<glib::DateTime>,
     ) -> Self {
@@ -98,7 +131,7 @@ impl NotificationItem {
             .property("summary", summary.into())
             .property("body", body.into())
             .property("actions", actions.into())
-            .property("hints", hints.into())
+            .property("hints", hints)
             .property("expire-timeout", expire_timeout)
             .property("timestamp", timestamp)
             .build();
@@ -113,11 +146,17 @@ impl NotificationItem {
         self.set_summary(notification.summary());
         self.set_body(notification.body());
         self.set_actions(notification.actions());
-        self.set_hints(notification.hints());
+        self.set_hints(notification.hints_variant());
         self.set_expire_timeout(notification.expire_timeout());
+        self.set_silent(notification.silent());
+        self.set_restored(notification.restored());
+        self.set_skip_history(notification.skip_history());
         if let Some(dt) = notification.timestamp() {
             self.set_timestamp(dt);
         }
+        if let Some(dt) = notification.updated() {
+            self.set_updated(dt);
+        }
     }
 
     pub fn from_variant(
@@ -156,7 +195,7 @@ impl NotificationItem {
                         summary,
                         body,
                         actions,
-                        hints,
+                        hints.end(),
                         expire_timeout,
                         datetime,
                     )
@@ -164,68 +203,153 @@ impl NotificationItem {
             )
     }
     pub fn get_hints(&self) -> NotificationHints {
-        self.hints().into()
+        self.hints_dict().into()
+    }
+
+    /// Returns the hints as an `a{sv}` variant.
+    pub fn hints_variant(&self) -> glib::Variant {
+        self.hints().unwrap_or_else(|| glib::VariantDict::new(None).end())
+    }
+
+    /// A copy of the hints to look values up in. Changes only reach the item
+    /// through `set_hints`.
+    pub fn hints_dict(&self) -> glib::VariantDict {
+        glib::VariantDict::new(Some(&self.hints_variant()))
+    }
+
+    /// Pairs up the flat `[key, label, key, label, ...]` action list the spec uses.
+    pub fn get_actions(&self) -> Vec<(String, String)> {
+        self.actions()
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect()
+    }
+
+    /// The desktop-entry hint, looked up on its own as `get_hints` also copies
+    /// any image data.
+    fn desktop_entry(&self) -> Option<String> {
+        self.hints_dict()
+            .lookup::<String>("desktop-entry")
+            .ok()
+            .flatten()
+            .filter(|entry| !entry.is_empty())
+    }
+
+    /// Notifications are grouped by their desktop-entry hint, or by app_name for
+    /// clients that don't send one.
+    pub fn group_key(&self) -> String {
+        self.desktop_entry().unwrap_or_else(|| self.app_name())
     }
 
-    pub fn get_image(&self) -> Option<gtk::gdk_pixbuf::Pixbuf> {
-        if let Some(image) = pic_pixbuf_from_hints(self.get_hints()) {
-            return Some(image);
+    /// The installed desktop entry named by the desktop-entry hint.
+    pub fn app_info(&self) -> Option<gio::DesktopAppInfo> {
+        let entry = self.desktop_entry()?;
+        if entry.ends_with(".desktop") {
+            return gio::DesktopAppInfo::new(&entry);
         }
+        gio::DesktopAppInfo::new(&format!("{entry}.desktop"))
+    }
+
+    /// Name to show for the sending application, the desktop entry's if there is one.
+    pub fn display_name(&self) -> String {
+        self.app_info()
+            .map(|info| info.display_name().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| self.app_name())
+    }
+
+    /// Icons that may represent the sending application, best first: app_icon as
+    /// a file URI, absolute path or icon theme name, then the desktop entry's icon.
+    /// Files that don't exist are skipped, theme names are left for the widget to check.
+    pub fn app_icon_candidates(&self) -> Vec<gio::Icon> {
+        let mut icons = Vec::new();
+
         let app_icon = self.app_icon();
-        if glib::Uri::is_valid(&app_icon, UriFlags::ENCODED_PATH).is_ok() {
-            let pixbuf = gio::File::for_uri(&app_icon)
-                .path()
-                .and_then(|p| gtk::gdk_pixbuf::Pixbuf::from_file(p).ok());
-            if pixbuf.is_some() {
-                return pixbuf;
+        match file_path(&app_icon) {
+            Some(path) if path.exists() => {
+                icons.push(gio::FileIcon::new(&gio::File::for_path(path)).upcast());
             }
+            Some(_) => {}
+            None if !app_icon.is_empty() => icons.push(gio::ThemedIcon::new(&app_icon).upcast()),
+            None => {}
         }
 
-        None
+        if let Some(icon) = self.app_info().and_then(|info| info.icon()) {
+            icons.push(icon);
+        }
+
+        icons
     }
-    pub fn get_image_square(&self) -> Option<Pixbuf> {
-        let img = self.get_image()?;
 
-        let w = img.width();
-        let h = img.height();
-        let s = w.min(h);
+    /// The picture shown with the notification, in the order of the spec:
+    /// image-data, image-path, app_icon and icon-data. Like in
+    /// `app_icon_candidates`, app_icon may be a file that exists or a theme name.
+    pub fn image_source(&self) -> Option<ImageSource> {
+        let hints = self.get_hints();
+        hints
+            .image_data
+            .map(ImageSource::Data)
+            .or_else(|| hints.image_path.as_deref().and_then(file_path).map(ImageSource::File))
+            .or_else(|| app_icon_path(&self.app_icon()).map(ImageSource::File))
+            .or_else(|| hints.icon_data.map(ImageSource::Data))
+    }
 
-        let x = (w - s) / 2;
-        let y = (h - s) / 2;
+    /// Hash of `image_source`, kept until the hints change.
+    pub fn image_hash(&self) -> Option<u64> {
+        let imp = self.imp();
+        if let Some(hash) = imp.image_hash.get() {
+            return hash;
+        }
 
-        Some(img.new_subpixbuf(x, y, s, s))
+        let hash = self.image_source().map(|source| {
+            let mut hasher = DefaultHasher::new();
+            source.hash(&mut hasher);
+            hasher.finish()
+        });
+        imp.image_hash.set(Some(hash));
+        hash
     }
 }
 
-pub fn pic_pixbuf_from_hints(hints: NotificationHints) -> Option<gdk_pixbuf::Pixbuf> {
-    let NotificationHints {
-        image_data,
-        image_path,
-        icon_data,
-        ..
-    } = hints;
-    image_data
-        .and_then(pixbuf_from_image_bytes)
-        .or_else(|| image_path.and_then(|path| gdk_pixbuf::Pixbuf::from_file(path).ok()))
-        .or_else(|| icon_data.and_then(pixbuf_from_image_bytes))
+/// Accepts absolute paths and file:// URIs.
+fn file_path(path: &str) -> Option<PathBuf> {
+    if path.starts_with('/') {
+        return Some(PathBuf::from(path));
+    }
+    if glib::Uri::is_valid(path, UriFlags::ENCODED_PATH).is_ok() {
+        return gio::File::for_uri(path).path();
+    }
+    None
 }
 
-fn pixbuf_from_image_bytes(bytes: NotificationImageData) -> Option<gdk_pixbuf::Pixbuf> {
-    let (width, height, rowstride, has_alpha, bits_per_sample, channels, data) = bytes;
-    let data = glib::Bytes::from_owned(data);
-    let colorspace = gdk_pixbuf::Colorspace::Rgb;
-    Some(gdk_pixbuf::Pixbuf::from_bytes(
-        &data,
-        colorspace,
-        has_alpha,
-        bits_per_sample,
-        width,
-        height,
-        rowstride,
-    ))
+/// File behind an app_icon, looking theme names up in the default display's
+/// icon theme so the image can be decoded off the main thread.
+fn app_icon_path(app_icon: &str) -> Option<PathBuf> {
+    if app_icon.is_empty() {
+        return None;
+    }
+    if let Some(path) = file_path(app_icon) {
+        return path.exists().then_some(path);
+    }
+
+    let theme = gtk::IconTheme::for_display(&gdk::Display::default()?);
+    if !theme.has_icon(app_icon) {
+        return None;
+    }
+    theme
+        .lookup_icon(
+            app_icon,
+            &[],
+            IMAGE_SIZE,
+            1,
+            gtk::TextDirection::None,
+            gtk::IconLookupFlags::empty(),
+        )
+        .file()?
+        .path()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Urgency {
     Low,
     Normal,
@@ -240,9 +364,16 @@ pub struct NotificationHints {
     pub desktop_entry: Option<String>,
     pub category: Option<String>,
     pub action_icons: Option<bool>,
+    pub resident: Option<bool>,
+    pub transient: Option<bool>,
     pub image_data: Option<NotificationImageData>,
     pub image_path: Option<String>,
     pub icon_data: Option<NotificationImageData>,
+    /// Progress in percent, from the `value` hint.
+    pub value: Option<i32>,
+    /// Notifications with the same tag replace each other, see
+    /// `x-canonical-private-synchronous` and `x-dunst-stack-tag`.
+    pub stack_tag: Option<String>,
 }
 
 impl From<glib::VariantDict> for NotificationHints {
@@ -268,6 +399,14 @@ impl From<glib::VariantDict> for NotificationHints {
             .lookup_value("action-icons", Some(&bool::static_variant_type()))
             .and_then(|v| v.get::<bool>());
 
+        let resident = dict
+            .lookup_value("resident", Some(&bool::static_variant_type()))
+            .and_then(|v| v.get::<bool>());
+
+        let transient = dict
+            .lookup_value("transient", Some(&bool::static_variant_type()))
+            .and_then(|v| v.get::<bool>());
+
         let image_data = dict
             .lookup_value("image-data", None)
             .and_then(|v| v.get::<NotificationImageData>());
@@ -280,14 +419,33 @@ impl From<glib::VariantDict> for NotificationHints {
             .lookup_value("icon-data", None)
             .and_then(|v| v.get::<NotificationImageData>());
 
+        // the spec says int, but unsigned values are common enough
+        let value = dict.lookup_value("value", None).and_then(|v| {
+            v.get::<i32>()
+                .or_else(|| v.get::<u32>().and_then(|v| i32::try_from(v).ok()))
+                .map(|v| v.clamp(0, 100))
+        });
+
+        let stack_tag = ["x-canonical-private-synchronous", "x-dunst-stack-tag"]
+            .iter()
+            .find_map(|key| {
+                dict.lookup_value(key, Some(&String::static_variant_type()))
+                    .and_then(|v| v.get::<String>())
+            })
+            .filter(|tag| !tag.is_empty());
+
         Self {
             urgency,
             desktop_entry,
             category,
             action_icons,
+            resident,
+            transient,
             image_data,
             image_path,
             icon_data: icon_data,
+            value,
+            stack_tag,
         }
     }
 }
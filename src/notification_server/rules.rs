@@ -0,0 +1,491 @@
+use crate::notification_server::notification::{NotificationItem, Urgency};
+use gio::prelude::{FileExt, FileMonitorExt};
+use gio::glib::variant::ToVariant;
+use gtk::gio;
+use gtk::glib::{self, Regex, RegexCompileFlags, RegexMatchFlags};
+use gtk::prelude::MediaStreamExt;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use thiserror::Error;
+
+const SOUND_THEME_DIR: &str = "/usr/share/sounds/freedesktop/stereo";
+
+#[derive(Error, Debug)]
+pub enum RulesError {
+    #[error("{0}")]
+    Glib(#[from] glib::Error),
+    #[error("rule {0}: unknown urgency {1}")]
+    Urgency(String, String),
+}
+
+/// What happens to a notification once every matching rule was applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Store the notification, playing the sound of the last matching rule
+    /// that has one.
+    Keep { sound: Option<String> },
+    Drop,
+}
+
+#[derive(Debug, Default)]
+struct Effects {
+    drop: bool,
+    silent: bool,
+    urgency: Option<Urgency>,
+    expire_timeout: Option<i32>,
+    rewrite_summary: Option<String>,
+    persist: bool,
+    sound: Option<String>,
+}
+
+/// One `[group]` of the rules file. Every given condition has to hold for the
+/// rule to match, app-name, desktop-entry, category and urgency are compared
+/// exactly, summary and body are regular expressions.
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    app_name: Option<String>,
+    desktop_entry: Option<String>,
+    category: Option<String>,
+    urgency: Option<Urgency>,
+    summary: Option<Regex>,
+    body: Option<Regex>,
+    effects: Effects,
+    hits: Cell<u64>,
+}
+
+impl Rule {
+    fn from_group(file: &glib::KeyFile, name: &str) -> Result<Self, RulesError> {
+        let string = |key: &str| file.string(name, key).ok().map(|s| s.to_string());
+        // taken as written, key file escapes would eat the backslashes of \w or \1
+        let raw = |key: &str| file.value(name, key).ok().map(|s| s.to_string());
+        let regex = |key: &str| -> Result<Option<Regex>, RulesError> {
+            let Some(pattern) = raw(key) else {
+                return Ok(None);
+            };
+            Ok(Regex::new(&pattern, RegexCompileFlags::OPTIMIZE, RegexMatchFlags::DEFAULT)?)
+        };
+        let urgency = |key: &str| -> Result<Option<Urgency>, RulesError> {
+            string(key)
+                .map(|value| parse_urgency(&value).ok_or(RulesError::Urgency(name.to_string(), value)))
+                .transpose()
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            app_name: string("app-name"),
+            desktop_entry: string("desktop-entry"),
+            category: string("category"),
+            urgency: urgency("urgency")?,
+            summary: regex("summary")?,
+            body: regex("body")?,
+            effects: Effects {
+                drop: file.boolean(name, "drop").unwrap_or(false),
+                silent: file.boolean(name, "silent").unwrap_or(false),
+                urgency: urgency("set-urgency")?,
+                expire_timeout: file.integer(name, "expire-timeout").ok(),
+                rewrite_summary: raw("rewrite-summary"),
+                persist: file.boolean(name, "persist").unwrap_or(true),
+                sound: string("sound"),
+            },
+            hits: Cell::new(0),
+        })
+    }
+
+    fn matches(&self, item: &NotificationItem) -> bool {
+        let hints = item.get_hints();
+        // Regex::match_ trips a debug assertion in glib-rs when nothing matches
+        let is_match = |regex: &Regex, text: String| {
+            Regex::match_simple(regex.pattern(), text, regex.compile_flags(), RegexMatchFlags::DEFAULT)
+        };
+
+        self.app_name.as_ref().map_or(true, |name| *name == item.app_name())
+            && self
+                .desktop_entry
+                .as_ref()
+                .map_or(true, |entry| hints.desktop_entry.as_ref() == Some(entry))
+            && self
+                .category
+                .as_ref()
+                .map_or(true, |category| hints.category.as_ref() == Some(category))
+            && self.urgency.map_or(true, |urgency| urgency == hints.urgency)
+            && self.summary.as_ref().map_or(true, |r| is_match(r, item.summary()))
+            && self.body.as_ref().map_or(true, |r| is_match(r, item.body()))
+    }
+
+    fn apply(&self, item: &NotificationItem) {
+        let effects = &self.effects;
+
+        if effects.silent {
+            item.set_silent(true);
+        }
+        if let Some(timeout) = effects.expire_timeout {
+            item.set_expire_timeout(timeout);
+        }
+        if let Some(summary) = &effects.rewrite_summary {
+            // with a summary pattern the rewrite may refer to its groups as \1, \2, ...
+            let rewritten = self
+                .summary
+                .as_ref()
+                .and_then(|r| r.replace(&item.summary(), 0, summary, RegexMatchFlags::DEFAULT).ok())
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| summary.clone());
+            item.set_summary(rewritten);
+        }
+
+        if !effects.persist {
+            item.set_skip_history(true);
+        }
+        if let Some(urgency) = effects.urgency {
+            let hints = item.hints_dict();
+            hints.insert_value("urgency", &urgency_byte(urgency).to_variant());
+            item.set_hints(hints.end());
+        }
+    }
+}
+
+/// Rules from `$XDG_CONFIG_HOME/shell/rules.ini`, applied to every incoming
+/// notification before it is stored. Each group is one rule:
+///
+/// ```ini
+/// [quiet chat]
+/// desktop-entry=org.telegram.desktop
+/// body=^(?i)typing
+/// silent=true
+/// expire-timeout=2000
+/// persist=false
+/// ```
+///
+/// Effects are `drop`, `silent`, `set-urgency`, `expire-timeout`,
+/// `rewrite-summary`, `persist` and `sound`.
+#[derive(Clone)]
+pub struct Rules {
+    path: Rc<PathBuf>,
+    rules: Rc<RefCell<Vec<Rule>>>,
+    monitor: Rc<OnceCell<gio::FileMonitor>>,
+    /// Keeps the last sound alive until it finished playing.
+    sound: Rc<RefCell<Option<gtk::MediaFile>>>,
+}
+
+impl Rules {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: Rc::new(path.as_ref().to_path_buf()),
+            rules: Rc::new(RefCell::new(Vec::new())),
+            monitor: Rc::new(OnceCell::new()),
+            sound: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Reads the rules file again. Hit counters are kept for rules whose name
+    /// did not change, a file that fails to parse keeps the previous rules.
+    pub fn load(&self) -> Result<(), RulesError> {
+        if !self.path.exists() {
+            self.rules.borrow_mut().clear();
+            return Ok(());
+        }
+
+        let file = glib::KeyFile::new();
+        file.load_from_file(self.path.as_ref(), glib::KeyFileFlags::NONE)?;
+
+        let mut rules = file
+            .groups()
+            .iter()
+            .map(|name| Rule::from_group(&file, name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let hits: HashMap<String, u64> = self
+            .rules
+            .borrow()
+            .iter()
+            .map(|rule| (rule.name.clone(), rule.hits.get()))
+            .collect();
+        for rule in &mut rules {
+            rule.hits.set(hits.get(&rule.name).copied().unwrap_or(0));
+        }
+
+        *self.rules.borrow_mut() = rules;
+        Ok(())
+    }
+
+    /// Loads the rules and reloads them whenever the file changes.
+    pub fn watch(&self) {
+        if let Err(e) = self.load() {
+            eprintln!("Could not load notification rules: {e}");
+        }
+
+        let file = gio::File::for_path(self.path.as_ref());
+        let monitor = match file.monitor_file(gio::FileMonitorFlags::WATCH_MOVES, None::<&gio::Cancellable>) {
+            Ok(monitor) => monitor,
+            Err(e) => {
+                eprintln!("Could not watch notification rules: {e}");
+                return;
+            }
+        };
+
+        let rules = self.clone();
+        monitor.connect_changed(move |_, _, _, event| {
+            if !matches!(
+                event,
+                gio::FileMonitorEvent::ChangesDoneHint
+                    | gio::FileMonitorEvent::Created
+                    | gio::FileMonitorEvent::Deleted
+                    | gio::FileMonitorEvent::MovedIn
+                    | gio::FileMonitorEvent::Renamed
+            ) {
+                return;
+            }
+            if let Err(e) = rules.load() {
+                eprintln!("Could not reload notification rules: {e}");
+            }
+        });
+        let _ = self.monitor.set(monitor);
+    }
+
+    /// Applies every matching rule to `item` in file order, a dropping rule ends
+    /// the evaluation.
+    pub fn apply(&self, item: &NotificationItem) -> Verdict {
+        let mut sound = None;
+
+        for rule in self.rules.borrow().iter() {
+            if !rule.matches(item) {
+                continue;
+            }
+            rule.hits.set(rule.hits.get() + 1);
+
+            if rule.effects.drop {
+                return Verdict::Drop;
+            }
+            rule.apply(item);
+            sound = rule.effects.sound.clone().or(sound);
+        }
+
+        Verdict::Keep { sound }
+    }
+
+    /// Rule names with the number of notifications they matched since startup.
+    pub fn hits(&self) -> Vec<(String, u64)> {
+        self.rules
+            .borrow()
+            .iter()
+            .map(|rule| (rule.name.clone(), rule.hits.get()))
+            .collect()
+    }
+
+    /// Plays a file, or a sound from the freedesktop theme when given a bare name.
+    pub fn play(&self, sound: &str) {
+        let path = if sound.contains('/') {
+            PathBuf::from(sound)
+        } else {
+            Path::new(SOUND_THEME_DIR).join(format!("{sound}.oga"))
+        };
+
+        let media = gtk::MediaFile::for_filename(path);
+        media.play();
+        *self.sound.borrow_mut() = Some(media);
+    }
+}
+
+fn parse_urgency(value: &str) -> Option<Urgency> {
+    match value.to_ascii_lowercase().as_str() {
+        "low" | "0" => Some(Urgency::Low),
+        "normal" | "1" => Some(Urgency::Normal),
+        "critical" | "2" => Some(Urgency::Critical),
+        _ => None,
+    }
+}
+
+fn urgency_byte(urgency: Urgency) -> u8 {
+    match urgency {
+        Urgency::Low => 0,
+        Urgency::Normal => 1,
+        Urgency::Critical => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rules read from a file holding `contents`, removed again right away.
+    fn rules(contents: &str) -> Result<Rules, RulesError> {
+        let dir = glib::mkdtemp(glib::tmp_dir().join("shell-rules-XXXXXX")).unwrap();
+        let path = dir.join("rules.ini");
+        std::fs::write(&path, contents).unwrap();
+
+        let rules = Rules::new(&path);
+        let result = rules.load();
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.map(|()| rules)
+    }
+
+    fn item(app_name: &str, summary: &str, body: &str, hints: &[(&str, glib::Variant)]) -> NotificationItem {
+        let dict = glib::VariantDict::new(None);
+        for (key, value) in hints {
+            dict.insert_value(key, value);
+        }
+        NotificationItem::new(Some(1), app_name, 0, "", summary, body, Vec::new(), dict.end(), -1, None)
+    }
+
+    #[test]
+    fn parses_conditions_and_effects() {
+        let rules = rules(
+            "[chat]\n\
+             app-name=Chat\n\
+             desktop-entry=org.example.Chat\n\
+             category=im.received\n\
+             urgency=LOW\n\
+             summary=^(\\w+) says\n\
+             silent=true\n\
+             set-urgency=2\n\
+             expire-timeout=2000\n\
+             rewrite-summary=\\1\n\
+             persist=false\n\
+             sound=message\n\
+             \n\
+             [empty]\n",
+        )
+        .unwrap();
+        let rules = rules.rules.borrow();
+
+        let [chat, empty] = &rules[..] else {
+            panic!("expected two rules, got {}", rules.len());
+        };
+        assert_eq!(chat.name, "chat");
+        assert_eq!(chat.app_name.as_deref(), Some("Chat"));
+        assert_eq!(chat.desktop_entry.as_deref(), Some("org.example.Chat"));
+        assert_eq!(chat.category.as_deref(), Some("im.received"));
+        assert_eq!(chat.urgency, Some(Urgency::Low));
+        assert_eq!(chat.summary.as_ref().map(|r| r.pattern().to_string()), Some("^(\\w+) says".into()));
+        assert!(chat.body.is_none());
+
+        let effects = &chat.effects;
+        assert!(!effects.drop);
+        assert!(effects.silent);
+        assert_eq!(effects.urgency, Some(Urgency::Critical));
+        assert_eq!(effects.expire_timeout, Some(2000));
+        assert_eq!(effects.rewrite_summary.as_deref(), Some("\\1"));
+        assert!(!effects.persist);
+        assert_eq!(effects.sound.as_deref(), Some("message"));
+
+        // a rule without conditions matches everything and changes nothing
+        assert!(empty.app_name.is_none() && empty.urgency.is_none() && empty.summary.is_none());
+        assert!(!empty.effects.drop && !empty.effects.silent && empty.effects.persist);
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        assert!(matches!(
+            rules("[bad]\nurgency=urgent\n"),
+            Err(RulesError::Urgency(name, value)) if name == "bad" && value == "urgent"
+        ));
+        assert!(matches!(rules("[bad]\nbody=(unclosed\n"), Err(RulesError::Glib(_))));
+        assert!(matches!(rules("not a key file"), Err(RulesError::Glib(_))));
+    }
+
+    #[test]
+    fn every_condition_has_to_match() {
+        let rules = rules(
+            "[app]\napp-name=Chat\n\
+             [entry]\ndesktop-entry=org.example.Chat\n\
+             [category]\ncategory=im.received\n\
+             [critical]\nurgency=critical\n\
+             [summary]\nsummary=^New\n\
+             [body]\nbody=(?i)urgent\n\
+             [both]\napp-name=Chat\nbody=urgent\n",
+        )
+        .unwrap();
+        let matching = |item: &NotificationItem| -> Vec<String> {
+            rules
+                .rules
+                .borrow()
+                .iter()
+                .filter(|rule| rule.matches(item))
+                .map(|rule| rule.name.clone())
+                .collect()
+        };
+
+        assert_eq!(matching(&item("Chat", "", "", &[])), ["app"]);
+        assert_eq!(matching(&item("chat", "", "", &[])), Vec::<String>::new());
+        assert_eq!(
+            matching(&item("", "", "", &[("desktop-entry", "org.example.Chat".to_variant())])),
+            ["entry"]
+        );
+        assert_eq!(
+            matching(&item("", "", "", &[("category", "im.received".to_variant())])),
+            ["category"]
+        );
+        assert_eq!(matching(&item("", "", "", &[("urgency", 2u8.to_variant())])), ["critical"]);
+        assert_eq!(matching(&item("", "New mail", "", &[])), ["summary"]);
+        assert_eq!(matching(&item("", "Re: New mail", "", &[])), Vec::<String>::new());
+        assert_eq!(matching(&item("", "", "an URGENT matter", &[])), ["body"]);
+        assert_eq!(matching(&item("Chat", "", "urgent", &[])), ["app", "body", "both"]);
+    }
+
+    #[test]
+    fn effects_are_applied_in_file_order() {
+        let rules = rules(
+            "[quiet]\napp-name=Chat\nsilent=true\nexpire-timeout=2000\nsound=first\n\
+             [rename]\nsummary=^(\\w+) says (.*)$\nrewrite-summary=\\1: \\2\nsound=second\n\
+             [escalate]\nbody=fire\nset-urgency=critical\npersist=false\n",
+        )
+        .unwrap();
+
+        let chat = item("Chat", "Ann says hi", "", &[]);
+        assert_eq!(rules.apply(&chat), Verdict::Keep { sound: Some("second".into()) });
+        assert!(chat.silent());
+        assert_eq!(chat.expire_timeout(), 2000);
+        assert_eq!(chat.summary(), "Ann: hi");
+        assert_eq!(chat.get_hints().urgency, Urgency::Normal);
+        assert!(!chat.skip_history());
+
+        let alarm = item("Alarm", "Smoke", "fire", &[("transient", false.to_variant())]);
+        assert_eq!(rules.apply(&alarm), Verdict::Keep { sound: None });
+        assert!(!alarm.silent());
+        assert_eq!(alarm.expire_timeout(), -1);
+        assert_eq!(alarm.get_hints().urgency, Urgency::Critical);
+        assert!(alarm.skip_history());
+        // the client's own hints are left as they were sent
+        assert_eq!(alarm.get_hints().transient, Some(false));
+
+        assert_eq!(rules.hits(), [("quiet".into(), 1), ("rename".into(), 1), ("escalate".into(), 1)]);
+    }
+
+    #[test]
+    fn dropping_ends_the_evaluation() {
+        let rules = rules("[spam]\napp-name=Spam\ndrop=true\n[all]\nsilent=true\n").unwrap();
+
+        let spam = item("Spam", "", "", &[]);
+        assert_eq!(rules.apply(&spam), Verdict::Drop);
+        assert!(!spam.silent());
+        assert_eq!(rules.hits(), [("spam".into(), 1), ("all".into(), 0)]);
+
+        assert_eq!(rules.apply(&item("Other", "", "", &[])), Verdict::Keep { sound: None });
+        assert_eq!(rules.hits(), [("spam".into(), 1), ("all".into(), 1)]);
+    }
+
+    #[test]
+    fn reloading_keeps_hits_of_rules_with_the_same_name() {
+        let dir = glib::mkdtemp(glib::tmp_dir().join("shell-rules-XXXXXX")).unwrap();
+        let path = dir.join("rules.ini");
+        std::fs::write(&path, "[kept]\n[renamed]\n").unwrap();
+        let rules = Rules::new(&path);
+        rules.load().unwrap();
+        rules.apply(&item("", "", "", &[]));
+
+        std::fs::write(&path, "[kept]\n[new name]\n").unwrap();
+        rules.load().unwrap();
+        assert_eq!(rules.hits(), [("kept".into(), 1), ("new name".into(), 0)]);
+
+        // a broken file keeps the previous rules
+        std::fs::write(&path, "[kept]\nurgency=soon\n").unwrap();
+        assert!(rules.load().is_err());
+        assert_eq!(rules.hits(), [("kept".into(), 1), ("new name".into(), 0)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        rules.load().unwrap();
+        assert!(rules.hits().is_empty());
+    }
+}
@@ -1,15 +1,28 @@
 use crate::notification_server::notification::NotificationItem;
-use crate::notification_server::store::IDStore;
-use gio::glib::object::{Cast, CastNone};
+use crate::notification_server::control;
+use crate::notification_server::history::History;
+use crate::notification_server::image::{self, ImageLimits};
+use crate::notification_server::rules::{Rules, Verdict};
+use crate::notification_server::store::{IDStore, IdPolicy};
+use crate::popups::Corner;
+use adw::subclass::prelude::ObjectSubclassIsExt;
+use gio::glib::object::{Cast, CastNone, IsA, ObjectExt};
 use gio::glib::property::PropertyGet;
+use gio::glib::variant::{FromVariant, StaticVariantType, ToVariant};
 use gio::glib::Variant;
-use gio::prelude::ListModelExt;
+use gio::prelude::{ActionExt, ActionMapExt, ListModelExt};
+use gtk::gdk::{self, prelude::DisplayExt};
 use gtk::gio::{self};
-use gtk::glib::{self};
-use std::cell::OnceCell;
+use gio::prelude::AppLaunchContextExt;
+use gtk::glib::{self, clone, Object};
+use crate::notification_server::notification::Urgency;
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
 use std::ffi::os_str::Display;
 use std::fmt::write;
 use std::str::FromStr;
+use std::path::PathBuf;
+use std::time::Duration;
 use std::{
     error::Error,
     sync::{
@@ -29,10 +42,137 @@ struct NotifyParams(
     i32,
 );
 
-#[derive(Clone)]
-pub struct Server {
-    store: gio::ListStore,
-    connection: OnceCell<gio::DBusConnection>,
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Timeout in ms used for notifications that send an expire_timeout of -1.
+    pub default_expire_timeout: u32,
+    /// Whether critical notifications are subject to expiry at all.
+    pub expire_critical: bool,
+    /// Maximum number of notifications kept in the history file, 0 disables it.
+    pub history_max_count: usize,
+    /// Notifications older than this are dropped from the history file.
+    pub history_max_age: Duration,
+    /// Whether ids of closed notifications may be handed out again right away.
+    pub id_policy: IdPolicy,
+    /// Key file with per-application rules, watched for changes.
+    pub rules_path: PathBuf,
+    /// Bounds for images clients send as raw pixels.
+    pub image_limits: ImageLimits,
+    /// Screen corner the popups of new notifications stack in.
+    pub popup_corner: Corner,
+    /// Number of popups shown at once, the oldest is dropped beyond that.
+    pub popup_max_visible: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            default_expire_timeout: 5000,
+            expire_critical: false,
+            history_max_count: 200,
+            history_max_age: Duration::from_secs(7 * 24 * 60 * 60),
+            id_policy: IdPolicy::Monotonic,
+            rules_path: glib::user_config_dir().join("shell").join("rules.ini"),
+            image_limits: ImageLimits::default(),
+            popup_corner: Corner::default(),
+            popup_max_visible: 3,
+        }
+    }
+}
+
+mod inner {
+    use super::*;
+    use glib::subclass::{object::ObjectImpl, object::ObjectImplExt, types::ObjectSubclass, Signal};
+    use glib::subclass::types::ObjectSubclassExt;
+    use glib::types::StaticType;
+    use std::sync::OnceLock;
+
+    pub struct Server {
+        pub store: IDStore,
+        pub connection: OnceCell<gio::DBusConnection>,
+        pub config: RefCell<ServerConfig>,
+        pub timers: RefCell<HashMap<u32, glib::SourceId>>,
+        pub history: OnceCell<History>,
+        pub rules: OnceCell<Rules>,
+        /// Stateful boolean action holding the Do Not Disturb state.
+        pub dnd: gio::SimpleAction,
+        /// Stateful u32 action counting notifications that arrived silently while DND was on.
+        pub missed: gio::SimpleAction,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Server {
+        const NAME: &'static str = "NotificationServer";
+        type Type = super::Server;
+        type ParentType = glib::Object;
+
+        fn new() -> Self {
+            // a boolean action without parameter toggles its state when activated
+            let dnd = gio::SimpleAction::new_stateful("dnd", None, &false.to_variant());
+            let missed = gio::SimpleAction::new_stateful("dnd-missed", None, &0u32.to_variant());
+
+            // activating the counter marks everything that arrived while muted as seen
+            missed.connect_activate(|action, _| action.set_state(&0u32.to_variant()));
+
+            Self {
+                store: IDStore::new::<NotificationItem>(),
+                connection: OnceCell::new(),
+                config: RefCell::new(ServerConfig::default()),
+                timers: RefCell::new(HashMap::new()),
+                history: OnceCell::new(),
+                rules: OnceCell::new(),
+                dnd,
+                missed,
+            }
+        }
+    }
+
+    impl ObjectImpl for Server {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: OnceLock<Vec<Signal>> = OnceLock::new();
+            SIGNALS.get_or_init(|| {
+                vec![
+                    // a notification was stored, either new or replacing an older one
+                    Signal::builder("notification-received")
+                        .param_types([NotificationItem::static_type()])
+                        .build(),
+                    // id and CloseReason of a notification that left the store
+                    Signal::builder("notification-closed")
+                        .param_types([u32::static_type(), u32::static_type()])
+                        .build(),
+                    // DND state and number of notifications missed while it was on
+                    Signal::builder("dnd-changed")
+                        .param_types([bool::static_type(), u32::static_type()])
+                        .build(),
+                ]
+            })
+        }
+
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+
+            self.dnd.connect_state_notify(clone!(
+                #[weak]
+                obj,
+                move |action| {
+                    if action_state(action).unwrap_or(false) {
+                        obj.imp().missed.set_state(&0u32.to_variant());
+                    }
+                    obj.emit_dnd_changed();
+                }
+            ));
+            self.missed.connect_state_notify(clone!(
+                #[weak]
+                obj,
+                move |_| obj.emit_dnd_changed()
+            ));
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct Server(ObjectSubclass<inner::Server>);
 }
 
 const NOTIFICATION_DBUS_NAME: &str = "org.freedesktop.Notifications";
@@ -40,6 +180,7 @@ const NOTIFICATION_DBUS_PATH: &str = "/org/freedesktop/Notifications";
 const NOTIFICATION_DBUS_INTERFACE: &str = "org.freedesktop.Notifications";
 const NOTIFICATION_INTROSPECTION_XML: &str = include_str!("notifications-introspect.xml");
 
+#[derive(Clone, Copy, Debug)]
 pub enum CloseReason {
     Expired = 1,
     Dismissed = 2,
@@ -93,16 +234,83 @@ impl std::error::Error for ServerError {
     }
 }
 
+fn action_state<T: FromVariant>(action: &gio::SimpleAction) -> Option<T> {
+    action.state().and_then(|s| s.get::<T>())
+}
+
 impl Server {
     pub fn new() -> Self {
-        Server {
-            store: IDStore::new::<NotificationItem>(),
-            connection: OnceCell::new(),
+        Self::with_config(ServerConfig::default())
+    }
+
+    pub fn with_config(config: ServerConfig) -> Self {
+        let obj: Server = Object::new();
+        let imp = obj.imp();
+
+        imp.store.set_policy(config.id_policy);
+        let _ = imp.history.set(History::new(&config));
+
+        let rules = Rules::new(&config.rules_path);
+        rules.watch();
+        let _ = imp.rules.set(rules);
+        *imp.config.borrow_mut() = config;
+
+        obj.restore_history();
+
+        if let Some(history) = imp.history.get().cloned() {
+            imp.store.connect_items_changed(move |store, _, _, _| {
+                history.schedule_save(store);
+            });
+        }
+
+        obj
+    }
+
+    fn restore_history(&self) {
+        let Some(history) = self.imp().history.get() else {
+            return;
+        };
+        let items = match history.load() {
+            Ok(items) => items,
+            Err(e) => {
+                eprintln!("Could not load notification history: {e}");
+                return;
+            }
+        };
+
+        for item in items {
+            self.imp().store.restore(item.id(), item);
         }
     }
 
+    pub fn dnd(&self) -> bool {
+        action_state(&self.imp().dnd).unwrap_or(false)
+    }
+
+    pub fn set_dnd(&self, enabled: bool) {
+        self.imp().dnd.change_state(&enabled.to_variant());
+    }
+
+    /// Number of notifications that were stored silently during the last DND period.
+    pub fn missed_count(&self) -> u32 {
+        action_state(&self.imp().missed).unwrap_or(0)
+    }
+
+    fn emit_dnd_changed(&self) {
+        self.emit_by_name::<()>("dnd-changed", &[&self.dnd(), &self.missed_count()]);
+    }
+
+    pub fn config(&self) -> ServerConfig {
+        self.imp().config.borrow().clone()
+    }
+
+    /// Bounds for images clients send, also applied when they are decoded.
+    pub fn image_limits(&self) -> ImageLimits {
+        self.imp().config.borrow().image_limits.clone()
+    }
+
     pub fn get_store(&self) -> IDStore {
-        self.store.clone()
+        self.imp().store.clone()
     }
 
     pub fn connect_to_dbus(&self) {
@@ -117,13 +325,20 @@ impl Server {
             move |conn, name| {
                 println!("Name acquired {conn:?} {name}");
                 Self::register_dbus_interface(&s, &conn).unwrap();
-                let _ = s.connection.set(conn);
+                let _ = s.imp().connection.set(conn);
             },
             |x, y| {
                 println!("Name lost {x:?} {y}");
             },
         );
     }
+    /// Exports the control interface on `conn`, the application's own connection,
+    /// so it stays reachable while another daemon owns the freedesktop name.
+    pub fn export_control(&self, conn: &gio::DBusConnection) {
+        if let Err(e) = control::register(self, conn) {
+            eprintln!("Could not export the control interface: {e}");
+        }
+    }
     fn register_dbus_interface(&self, conn: &gio::DBusConnection) -> Result<(), ServerError> {
         let node_info = gio::DBusNodeInfo::for_xml(NOTIFICATION_INTROSPECTION_XML)?;
         let interface_info = node_info
@@ -186,37 +401,325 @@ impl Server {
     }
 
     fn handle_insert_notification(&self, notification: &NotificationItem) -> u32 {
-        let replaces_id = notification.replaces_id();
-        // replaces id starts at 1 -> n_items == replaces_id -> last item
+        // an id we never issued or that was closed already is treated as a new
+        // notification, as the spec demands
+        let replaced = match notification.replaces_id() {
+            0 => self.find_stacked(notification),
+            id => self.imp().store.get(id).and_downcast::<NotificationItem>(),
+        };
 
-        println!("handling insert");
-        if replaces_id == 0 {
-            println!("trying to push");
-            let (id, prev) = self.store.push(notification.clone());
+        let Some(replaced) = replaced else {
+            let id = self.imp().store.push(notification.clone());
             notification.set_id(id);
+            self.schedule_expiry(notification);
             return id;
+        };
+
+        let replaces_id = replaced.id();
+        notification.set_id(replaces_id);
+        if let Some(first_seen) = replaced.timestamp() {
+            if let Some(updated) = notification.timestamp() {
+                notification.set_updated(updated);
+            }
+            notification.set_timestamp(first_seen);
         }
-        
-        println!("trying to insert");
-        self.store.set(replaces_id, notification.clone());
+
+        self.imp().store.set(replaces_id, notification.clone());
+        self.schedule_expiry(notification);
 
         replaces_id
     }
 
+    /// The stored notification sharing the stack tag of `notification`, which
+    /// replaces it even though the client doesn't know its id.
+    fn find_stacked(&self, notification: &NotificationItem) -> Option<NotificationItem> {
+        let tag = notification.get_hints().stack_tag?;
+        let store = &self.imp().store;
+        (0..store.n_items())
+            .filter_map(|i| store.item(i).and_downcast::<NotificationItem>())
+            .find(|item| item.get_hints().stack_tag.as_ref() == Some(&tag))
+    }
+
+    /// While DND is on everything below critical urgency is stored without
+    /// drawing attention to itself and counted for when DND is turned off.
+    fn apply_dnd(&self, notification: &NotificationItem) {
+        if !self.dnd() || matches!(notification.get_hints().urgency, Urgency::Critical) {
+            return;
+        }
+
+        notification.set_silent(true);
+        self.imp()
+            .missed
+            .set_state(&(self.missed_count() + 1).to_variant());
+    }
+
+    /// Number of notifications each rule matched, in file order.
+    pub fn rule_hits(&self) -> Vec<(String, u64)> {
+        self.imp().rules.get().map(Rules::hits).unwrap_or_default()
+    }
+
+    /// Resolves the spec's expire_timeout semantics to an actual duration,
+    /// `None` meaning the notification stays until it is closed.
+    pub fn expiry_for(&self, notification: &NotificationItem) -> Option<Duration> {
+        let config = self.imp().config.borrow();
+        if matches!(notification.get_hints().urgency, Urgency::Critical)
+            && !config.expire_critical
+        {
+            return None;
+        }
+
+        let ms = match notification.expire_timeout() {
+            0 => return None,
+            t if t < 0 => config.default_expire_timeout,
+            t => t as u32,
+        };
+
+        Some(Duration::from_millis(ms.into()))
+    }
+
+    fn schedule_expiry(&self, notification: &NotificationItem) {
+        let id = notification.id();
+        self.cancel_expiry(id);
+
+        let Some(timeout) = self.expiry_for(notification) else {
+            return;
+        };
+
+        let source = glib::timeout_add_local_once(
+            timeout,
+            clone!(
+                #[weak(rename_to = s)]
+                self,
+                move || {
+                    // the source is destroyed once this returns, so only forget about it
+                    s.imp().timers.borrow_mut().remove(&id);
+                    s.close_notification(id, CloseReason::Expired);
+                }
+            ),
+        );
+
+        self.imp().timers.borrow_mut().insert(id, source);
+    }
+
+    /// Holds the expiry of a notification, e.g. while the pointer rests on it.
+    /// Resuming starts the full timeout again.
+    pub fn set_expiry_paused(&self, id: u32, paused: bool) {
+        if paused {
+            self.cancel_expiry(id);
+            return;
+        }
+        if let Some(notification) = self.imp().store.get(id).and_downcast::<NotificationItem>() {
+            self.schedule_expiry(&notification);
+        }
+    }
+
+    fn cancel_expiry(&self, id: u32) {
+        let source = self.imp().timers.borrow_mut().remove(&id);
+        if let Some(source) = source {
+            source.remove();
+        }
+    }
+
+    /// Registers the actions widgets use to talk back to the server on `map`:
+    /// `invoke-action((us))` emits ActionInvoked for the given id and key if
+    /// the notification offers that action,
+    /// `close-notification(u)` dismisses the notification with that id,
+    /// `clear-notifications` dismisses all of them,
+    /// `dismiss-group(s)` dismisses every notification of one group,
+    /// `dnd` toggles Do Not Disturb and `dnd-missed` resets the muted counter
+    /// and `reload-rules` rereads the rules file.
+    pub fn add_actions(&self, map: &impl IsA<gio::ActionMap>) {
+        let close = gio::SimpleAction::new("close-notification", Some(&u32::static_variant_type()));
+        close.connect_activate(clone!(
+            #[weak(rename_to = s)]
+            self,
+            move |_, param| {
+                let Some(id) = param.and_then(|p| p.get::<u32>()) else {
+                    return;
+                };
+                s.close_notification(id, CloseReason::Dismissed);
+            }
+        ));
+        map.add_action(&close);
+
+        let clear = gio::SimpleAction::new("clear-notifications", None);
+        clear.connect_activate(clone!(
+            #[weak(rename_to = s)]
+            self,
+            move |_, _| s.clear_all()
+        ));
+        map.add_action(&clear);
+
+        let dismiss_group =
+            gio::SimpleAction::new("dismiss-group", Some(&String::static_variant_type()));
+        dismiss_group.connect_activate(clone!(
+            #[weak(rename_to = s)]
+            self,
+            move |_, param| {
+                let Some(key) = param.and_then(|p| p.get::<String>()) else {
+                    return;
+                };
+                s.dismiss_group(&key);
+            }
+        ));
+        map.add_action(&dismiss_group);
+
+        map.add_action(&self.imp().dnd);
+        map.add_action(&self.imp().missed);
+
+        let reload_rules = gio::SimpleAction::new("reload-rules", None);
+        reload_rules.connect_activate(clone!(
+            #[weak(rename_to = s)]
+            self,
+            move |_, _| {
+                let Some(rules) = s.imp().rules.get() else {
+                    return;
+                };
+                if let Err(e) = rules.load() {
+                    eprintln!("Could not reload notification rules: {e}");
+                }
+            }
+        ));
+        map.add_action(&reload_rules);
+
+        let invoke = gio::SimpleAction::new(
+            "invoke-action",
+            Some(&<(u32, String)>::static_variant_type()),
+        );
+        invoke.connect_activate(clone!(
+            #[weak(rename_to = s)]
+            self,
+            move |_, param| {
+                let Some((id, key)) = param.and_then(|p| p.get::<(u32, String)>()) else {
+                    return;
+                };
+                s.invoke_action(id, &key);
+            }
+        ));
+        map.add_action(&invoke);
+    }
+
+    /// Emits ActionInvoked for `key` and closes the notification afterwards,
+    /// unless it asked to stay around through the resident hint.
+    /// An ActivationToken is sent first so the client is allowed to raise its window.
+    /// Keys the notification doesn't offer are refused, so no other peer on the
+    /// bus can send the client actions it never advertised.
+    pub fn invoke_action(&self, id: u32, key: &str) -> bool {
+        let Some(notification) = self.imp().store.get(id).and_downcast::<NotificationItem>() else {
+            return false;
+        };
+        if !notification.get_actions().iter().any(|(action, _)| action == key) {
+            return false;
+        }
+
+        if let Some(token) = Self::activation_token() {
+            if let Err(e) = self.send_signal("ActivationToken", &(id, token.as_str()).into()) {
+                eprintln!("Error occured sending activation token for notification: {e}");
+            }
+        }
+
+        if let Err(e) = self.send_signal("ActionInvoked", &(id, key).into()) {
+            eprintln!("Error occured sending action signal for notification: {e}");
+            return false;
+        }
+
+        if !notification.get_hints().resident.unwrap_or(false) {
+            self.close_notification(id, CloseReason::Dismissed);
+        }
+
+        true
+    }
+
+    fn activation_token() -> Option<glib::GString> {
+        let context = gdk::Display::default()?.app_launch_context();
+        context.startup_notify_id(None::<&gio::AppInfo>, &[])
+    }
+
+    /// Dismisses every stored notification.
+    pub fn clear_all(&self) {
+        let ids: Vec<u32> = (0..self.imp().store.n_items())
+            .filter_map(|i| self.imp().store.item(i).and_downcast::<NotificationItem>())
+            .map(|n| n.id())
+            .collect();
+
+        for id in ids {
+            self.close_notification(id, CloseReason::Dismissed);
+        }
+    }
+
+    /// Dismisses all notifications whose `NotificationItem::group_key` is `key`.
+    pub fn dismiss_group(&self, key: &str) {
+        let ids: Vec<u32> = (0..self.imp().store.n_items())
+            .filter_map(|i| self.imp().store.item(i).and_downcast::<NotificationItem>())
+            .filter(|n| n.group_key() == key)
+            .map(|n| n.id())
+            .collect();
+
+        for id in ids {
+            self.close_notification(id, CloseReason::Dismissed);
+        }
+    }
+
+    /// Removes the notification from the store and tells clients why it is gone.
+    /// Returns false if no notification with that id was stored.
+    pub fn close_notification(&self, id: u32, reason: CloseReason) -> bool {
+        self.cancel_expiry(id);
+
+        if self.imp().store.remove(id).is_none() {
+            return false;
+        }
+
+        self.emit_by_name::<()>("notification-closed", &[&id, &(reason as u32)]);
+
+        if let Err(e) = self.send_closed(id, reason) {
+            eprintln!("Error occured sending close signal for notification: {e}")
+        };
+
+        true
+    }
+
     async fn on_notify(&self, parameters: &glib::Variant, invocation: gio::DBusMethodInvocation) {
         let dt = glib::DateTime::now_local().ok();
-        match NotificationItem::from_variant(None, parameters, dt) {
-            Some(notification) => {
-                let id = self.handle_insert_notification(&notification);
+        let Some(notification) = NotificationItem::from_variant(None, parameters, dt) else {
+            invocation.return_error(
+                gio::DBusError::InvalidArgs,
+                "Could not parse notification parameters",
+            );
+            return;
+        };
+
+        let verdict = self
+            .imp()
+            .rules
+            .get()
+            .map_or(Verdict::Keep { sound: None }, |rules| rules.apply(&notification));
+
+        let sound = match verdict {
+            Verdict::Keep { sound } => sound,
+            Verdict::Drop => {
+                // the client still gets an id, and learns right away that it is gone
+                let id = self.imp().store.reserve();
                 invocation.return_value(Some(&(id,).into()));
+                if let Err(e) = self.send_closed(id, CloseReason::Undefined) {
+                    eprintln!("Error occured sending close signal for notification: {e}")
+                }
+                return;
             }
-            None => {
-                invocation.return_error(
-                    gio::DBusError::InvalidArgs,
-                    "Could not parse notification parameters",
-                );
+        };
+
+        image::sanitize_hints(&notification, &self.imp().config.borrow().image_limits);
+        self.apply_dnd(&notification);
+
+        // silent notifications, whether by a rule or DND, make no noise either
+        if let Some(sound) = sound.filter(|_| !notification.silent() && !self.dnd()) {
+            if let Some(rules) = self.imp().rules.get() {
+                rules.play(&sound);
             }
         }
+
+        let id = self.handle_insert_notification(&notification);
+        invocation.return_value(Some(&(id,).into()));
+        self.emit_by_name::<()>("notification-received", &[&notification]);
     }
 
     pub fn send_closed(&self, id: u32, reason: CloseReason) -> Result<(), ServerError> {
@@ -229,6 +732,7 @@ impl Server {
             "actions",
             "body",
             "body-hyperlinks",
+            "body-markup",
             "persistent",
         ],)
             .into();
@@ -240,11 +744,12 @@ impl Server {
         args: &glib::Variant,
     ) -> Result<(), ServerError> {
         let conn = self
+            .imp()
             .connection
             .get()
             .ok_or(ServerError::ConnectionUninitialised)?;
         conn.emit_signal(
-            Some(NOTIFICATION_DBUS_NAME),
+            None,
             NOTIFICATION_DBUS_PATH,
             NOTIFICATION_DBUS_INTERFACE,
             signal.as_ref(),
@@ -257,14 +762,12 @@ impl Server {
         parameters: &glib::Variant,
         invocation: gio::DBusMethodInvocation,
     ) {
-        let Some(id) = parameters.get::<u32>() else {
+        let Some((id,)) = parameters.get::<(u32,)>() else {
             invocation.return_error(gio::DBusError::InvalidArgs, "Invalid Notification ID");
             return;
         };
 
-        let prev = self.store.remove(id);
-
-        if prev.is_none() {
+        if !self.close_notification(id, CloseReason::Call) {
             invocation.return_error(
                 gio::DBusError::Failed,
                 &format!("notification with id {id} not found"),
@@ -273,9 +776,5 @@ impl Server {
         };
 
         invocation.return_value(None);
-
-        if let Err(e) = self.send_closed(id, CloseReason::Call) {
-            eprintln!("Error occured sending close signal for notification: {e}")
-        };
     }
 }
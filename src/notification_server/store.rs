@@ -1,35 +1,28 @@
 use adw::subclass::prelude::ObjectSubclassIsExt;
-use gdk_pixbuf::Pixbuf;
-use gio::{
-    glib::{object::IsA, property::PropertyGet, variant::StaticVariantType, UriFlags},
-    prelude::FileExt,
+use gio::glib::{
+    object::{Cast, IsA},
+    types::StaticType,
 };
 use gtk::glib::{self, Object};
+use std::num::NonZeroU32;
+
+pub use inner::IdPolicy;
 
 mod inner {
 
-    use super::*;
-    use crate::notification_server::server::ServerError;
-    use crate::notification_server::NotificationItem;
-    use adw::subclass::prelude::ObjectSubclassExt;
-    use gio::glib::object::IsA;
     use gio::glib::subclass::types::ObjectSubclass;
-    use gio::glib::subclass::{Signal, SignalId};
-    use gio::glib::types::StaticType;
     use gio::prelude::ListModelExt;
     use gio::subclass::prelude::ListModelImpl;
-    use glib::prelude::ObjectExt;
-    use glib::subclass::object::{DerivedObjectProperties, ObjectImpl, ObjectImplExt};
-    use glib::{self, Properties};
-    use std::cell::{Cell, OnceCell, RefCell};
-    use std::collections::{HashMap, HashSet};
+    use glib::subclass::object::{ObjectImpl, ObjectImplExt};
+    use glib::subclass::types::ObjectSubclassExt;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
     use std::hash::Hash;
-    use std::num::{NonZero, NonZeroU32};
-    use std::ops::Div;
-    use std::rc::Rc;
     use thiserror::Error;
 
-    trait Key: Eq + Hash + Copy {
+    use super::*;
+
+    pub trait Key: Eq + Ord + Hash + Copy {
         const START: Self;
         fn next(&mut self) -> Self;
     }
@@ -57,7 +50,7 @@ mod inner {
     }
 
     #[derive(Error, Debug)]
-    enum StoreError {
+    pub enum StoreError {
         #[error("store is malformed")]
         Malformed,
         #[error("previous node not found")]
@@ -66,28 +59,63 @@ mod inner {
         NextNodeNotFound,
     }
 
-    struct Store<K: Key, T: Clone> {
+    /// How the store hands out ids for new items.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum IdPolicy {
+        /// Count upwards and wrap around, skipping ids that are still in use, so a
+        /// closed id is not handed to an unrelated notification right away.
+        #[default]
+        Monotonic,
+        /// Hand out the most recently freed id first.
+        Reuse,
+    }
+
+    pub struct Store<K: Key, T: Clone> {
         items: HashMap<K, Node<K, T>>,
+        policy: IdPolicy,
         free_ids: Vec<K>,
         id: K,
         head: Option<K>,
         tail: Option<K>,
-        cache: Option<(usize, K)>,
+        cache: Cell<Option<(usize, K)>>,
     }
 
     impl<K: Key, T: Clone> Store<K, T> {
         pub fn new() -> Self {
+            Self::with_policy(IdPolicy::default())
+        }
+        pub fn with_policy(policy: IdPolicy) -> Self {
             Self {
                 items: HashMap::new(),
+                policy,
                 free_ids: Vec::new(),
                 id: K::START,
                 head: None,
                 tail: None,
-                cache: None,
+                cache: Cell::new(None),
+            }
+        }
+        /// Switches how new ids are handed out, keeping the stored items.
+        pub fn set_policy(&mut self, policy: IdPolicy) {
+            self.policy = policy;
+            if policy == IdPolicy::Monotonic {
+                self.free_ids.clear();
             }
         }
         pub fn next_id(&mut self) -> K {
-            self.free_ids.pop().unwrap_or_else(|| self.id.next())
+            if let Some(id) = self.free_ids.pop() {
+                return id;
+            }
+
+            loop {
+                let id = self.id.next();
+                if !self.items.contains_key(&id) {
+                    return id;
+                }
+            }
+        }
+        pub fn len(&self) -> usize {
+            self.items.len()
         }
         fn initialize(&mut self, item: T, id: K) {
             self.items.insert(
@@ -103,12 +131,16 @@ mod inner {
         }
         pub fn push_tail(&mut self, item: T) -> K {
             let id = self.next_id();
+            self.cache.set(None);
             let (Some(_), Some(tail)) = (self.head, self.tail) else {
-                // TODO: Handle rewrap
                 self.initialize(item, id);
                 return id;
             };
 
+            if let Some(tn) = self.items.get_mut(&tail) {
+                tn.next = Some(id);
+            }
+
             self.items.insert(
                 id,
                 Node {
@@ -122,11 +154,16 @@ mod inner {
         }
         pub fn push_head(&mut self, item: T) -> K {
             let id = self.next_id();
+            self.cache.set(None);
             let (Some(head), Some(_)) = (self.head, self.tail) else {
-                // TODO: Handle rewrap
                 self.initialize(item, id);
                 return id;
             };
+
+            if let Some(hn) = self.items.get_mut(&head) {
+                hn.prev = Some(id);
+            }
+
             self.items.insert(
                 id,
                 Node {
@@ -138,16 +175,56 @@ mod inner {
             self.head = Some(id);
             id
         }
+        /// Appends `item` under an id that was handed out earlier, e.g. by a previous
+        /// session. Items keep the order they were inserted in and the id won't be
+        /// handed out again while the item lives, but later ids aren't necessarily
+        /// greater: the counter wraps around and `IdPolicy::Reuse` recycles ids.
+        pub fn insert_tail(&mut self, id: K, item: T) -> bool {
+            if self.items.contains_key(&id) {
+                return false;
+            }
+            self.cache.set(None);
+            self.free_ids.retain(|free| *free != id);
+            if id >= self.id {
+                self.id = id;
+                self.id.next();
+            }
+
+            let Some(tail) = self.tail else {
+                self.initialize(item, id);
+                return true;
+            };
+
+            if let Some(tn) = self.items.get_mut(&tail) {
+                tn.next = Some(id);
+            }
+
+            self.items.insert(
+                id,
+                Node {
+                    prev: Some(tail),
+                    next: None,
+                    value: item,
+                },
+            );
+            self.tail = Some(id);
+            true
+        }
         pub fn replace(&mut self, id: &K, item: T) -> Option<T> {
             let node = self.items.get_mut(id)?;
             let prev = std::mem::replace(&mut node.value, item);
             Some(prev)
         }
 
+        pub fn get(&self, id: &K) -> Option<&T> {
+            self.items.get(id).map(|n| &n.value)
+        }
+
         pub fn remove(&mut self, id: &K) -> Result<Option<T>, StoreError> {
             let Some(node) = self.items.remove(id) else {
                 return Ok(None);
             };
+            self.cache.set(None);
 
             match node {
                 Node {
@@ -198,187 +275,348 @@ mod inner {
 
                     self.head = Some(next);
                 }
-
+                // node is the only one left
                 Node {
                     prev: None,
                     next: None,
                     ..
                 } => {
-                    return Err(StoreError::Malformed);
+                    if Some(*id) != self.head || Some(*id) != self.tail {
+                        return Err(StoreError::Malformed);
+                    }
+
+                    self.head = None;
+                    self.tail = None;
                 }
             };
 
-            self.free_ids.push(*id);
+            if self.policy == IdPolicy::Reuse {
+                self.free_ids.push(*id);
+            }
 
             Ok(Some(node.value))
         }
 
-        fn try_cache_lookup(&self, pos: usize) -> Option<&Node<K, T>> {
-            let (cache_pos, cache_id) = self.cache?;
-            let cached_node = self.items.get(&cache_id);
+        /// Position of `id` counted from the head, or `None` if it is not stored.
+        pub fn position(&self, id: &K) -> Option<usize> {
+            if !self.items.contains_key(id) {
+                return None;
+            }
+            self.ids().position(|k| k == *id)
+        }
+
+        /// Iterates the stored ids from head to tail.
+        pub fn ids(&self) -> impl Iterator<Item = K> + '_ {
+            std::iter::successors(self.head, |id| self.items.get(id).and_then(|n| n.next))
+        }
+
+        fn try_cache_lookup(&self, pos: usize) -> Option<K> {
+            let (cache_pos, cache_id) = self.cache.get()?;
+            let cached_node = self.items.get(&cache_id)?;
 
             if pos == cache_pos {
-                return cached_node;
+                return Some(cache_id);
             }
-            if pos > cache_pos {
-                return self.items.get(&cached_node?.next?);
+            if pos == cache_pos + 1 {
+                return cached_node.next;
             }
-            if pos < cache_pos {
-                return self.items.get(&cached_node?.prev?);
+            if pos + 1 == cache_pos {
+                return cached_node.prev;
             }
 
             None
         }
 
-        pub fn nth_item(&self, pos: usize) -> Option<&T> {
-            //TODO: use caching adapted to the lookup pattern of the SelectionModel, use end if nearer
-
-            if let Some(node) = self.try_cache_lookup(pos) {
-                return Some(&node.value);
-            }
-
+        pub fn nth_id(&self, pos: usize) -> Option<K> {
             let len = self.items.len();
 
             if pos >= len {
                 return None;
             }
 
-            if pos < len / 2 {
-                return self.nth_item_from_head(pos);
-            }
+            // the ListView walks the model sequentially, so the neighbour of the
+            // last lookup is by far the most common request
+            let id = self
+                .try_cache_lookup(pos)
+                .or_else(|| {
+                    if pos < len / 2 {
+                        self.nth_id_from_head(pos)
+                    } else {
+                        self.nth_id_from_tail(pos, len)
+                    }
+                })?;
 
-            return self.nth_item_from_tail(pos, len);
+            self.cache.set(Some((pos, id)));
+            Some(id)
         }
-        fn nth_item_from_head(&self, pos: usize) -> Option<&T> {
+
+        pub fn nth_item(&self, pos: usize) -> Option<&T> {
+            let id = self.nth_id(pos)?;
+            self.items.get(&id).map(|n| &n.value)
+        }
+        fn nth_id_from_head(&self, pos: usize) -> Option<K> {
             let mut id = self.head?;
 
             for _ in 0..pos {
                 id = self.items.get(&id).and_then(|n| n.next)?;
             }
 
-            self.items.get(&id).map(|n| &n.value)
+            Some(id)
         }
-        fn nth_item_from_tail(&self, pos: usize, len: usize) -> Option<&T> {
+        fn nth_id_from_tail(&self, pos: usize, len: usize) -> Option<K> {
             let mut id = self.tail?;
 
-            for _ in pos..len {
-                id = self.items.get(&id).and_then(|n| n.next)?;
+            for _ in pos + 1..len {
+                id = self.items.get(&id).and_then(|n| n.prev)?;
             }
 
-            self.items.get(&id).map(|n| &n.value)
+            Some(id)
         }
     }
 
-    // #[derive(Properties)]
-    // #[properties(wrapper_type = super::IDStore)]
-    // pub struct IDStore {
-    //     items: RefCell<LinkedList<Object>>,
-    //     item_type: Cell<glib::Type>,
-    //     id_to_lli: RefCell<HashMap<>>
-    // }
-
-    // #[glib::object_subclass]
-    // impl ObjectSubclass for IDStore {
-    //     const NAME: &'static str = "IDStore";
-    //     type Type = super::IDStore;
-    //     type Interfaces = (gio::ListModel,);
-    //     type ParentType = Object;
-
-    //     fn new() -> Self {
-    //         Self {
-    //             items: RefCell::new(LinkedList::new()),
-
-    //             item_type: Cell::new(glib::Type::OBJECT)
-    //         }
-    //     }
-    // }
-    // // #[glib::derived_properties]
-    // impl ObjectImpl for IDStore {
-
-    //     fn constructed(&self) {
-    //         self.parent_constructed();
-    //     }
-    // }
-
-    // impl ListModelImpl for IDStore {
-    //     fn item(&self, position: u32) -> Option<Object> {
-    //         self.get_obj_by_pos(position as usize)
-    //     }
-    //     fn item_type(&self) -> glib::Type {
-    //         self.item_type.get()
-    //     }
-    //     fn n_items(&self) -> u32 {
-    //         let items = self.items.borrow();
-    //         items.len() as u32
-    //     }
-    // }
-
-    // impl IDStore {
-    //     pub fn push(&self, obj: Object) -> (u32, Option<Object>) {
-    //         let mut items = self.items.borrow_mut();
-    //         let id = items.push_front(obj);
-    //         let x = id.data
-    //     }
-
-    //     fn notify_items_changed(&self, position: u32, removed: u32, added: u32) {
-    //         println!("attempting items changed");
-    //         println!("added: {added}, deleted: {removed} at {position}");
-    //         self.obj().items_changed(position, removed, added);
-    //         println!("items_changed succeded");
-    //     }
-
-    //     pub fn remove(&self, position: usize) -> Option<Object> {
-    //         let id  = self.get_id_by_pos(position);
-    //         let mut items = self.items.borrow_mut();
-    //         let prev = items.remove(&id);
-    //         drop(items);
-
-    //         if prev.is_some() {
-
-    //             self.notify_items_changed(id, 1, 1);
-    //         }
-
-    //         prev
-    //     }
-
-    //     pub fn set(&self, id: u32, obj: Object) -> Option<Object> {
-    //         let mut items = self.items.borrow_mut();
-    //         let prev = items.insert(id, obj.into());
-    //         println!("items: {:?}", items);
-    //         drop(items);
-    //         let removed = prev.is_some() as u32;
-    //         self.notify_items_changed(id, removed, 1);
-    //         prev
-    //     }
-    //     pub fn set_type(&self, t: glib::Type) {
-    //         self.item_type.set(t);
-    //     }
-    //     pub fn mep(&self) {
-    //         println!("a");
-    //     }
-    // }
+    pub struct IDStore {
+        items: RefCell<Store<NonZeroU32, Object>>,
+        item_type: Cell<glib::Type>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for IDStore {
+        const NAME: &'static str = "IDStore";
+        type Type = super::IDStore;
+        type Interfaces = (gio::ListModel,);
+        type ParentType = Object;
+
+        fn new() -> Self {
+            Self {
+                items: RefCell::new(Store::new()),
+                item_type: Cell::new(Object::static_type()),
+            }
+        }
+    }
+
+    impl ObjectImpl for IDStore {
+        fn constructed(&self) {
+            self.parent_constructed();
+        }
+    }
+
+    impl ListModelImpl for IDStore {
+        fn item(&self, position: u32) -> Option<Object> {
+            self.items.borrow().nth_item(position as usize).cloned()
+        }
+        fn item_type(&self) -> glib::Type {
+            self.item_type.get()
+        }
+        fn n_items(&self) -> u32 {
+            self.items.borrow().len() as u32
+        }
+    }
+
+    impl IDStore {
+        fn notify_items_changed(&self, position: usize, removed: u32, added: u32) {
+            self.obj().items_changed(position as u32, removed, added);
+        }
+
+        pub fn push(&self, obj: Object) -> NonZeroU32 {
+            let id = self.items.borrow_mut().push_head(obj);
+            self.notify_items_changed(0, 0, 1);
+            id
+        }
+
+        pub fn reserve(&self) -> NonZeroU32 {
+            self.items.borrow_mut().next_id()
+        }
+
+        pub fn restore(&self, id: NonZeroU32, obj: Object) -> bool {
+            let mut items = self.items.borrow_mut();
+            if !items.insert_tail(id, obj) {
+                return false;
+            }
+            let position = items.len() - 1;
+            drop(items);
+
+            self.notify_items_changed(position, 0, 1);
+            true
+        }
+
+        pub fn set(&self, id: NonZeroU32, obj: Object) -> Option<Object> {
+            let mut items = self.items.borrow_mut();
+            let position = items.position(&id)?;
+            let prev = items.replace(&id, obj);
+            drop(items);
+
+            self.notify_items_changed(position, 1, 1);
+            prev
+        }
+
+        pub fn remove(&self, id: NonZeroU32) -> Option<Object> {
+            let mut items = self.items.borrow_mut();
+            let position = items.position(&id)?;
+            let prev = match items.remove(&id) {
+                Ok(prev) => prev,
+                Err(e) => {
+                    eprintln!("failed to remove {id} from store: {e}");
+                    return None;
+                }
+            };
+            drop(items);
+
+            self.notify_items_changed(position, 1, 0);
+            prev
+        }
+
+        pub fn get(&self, id: NonZeroU32) -> Option<Object> {
+            self.items.borrow().get(&id).cloned()
+        }
+
+        pub fn position(&self, id: NonZeroU32) -> Option<usize> {
+            self.items.borrow().position(&id)
+        }
+
+        pub fn set_type(&self, t: glib::Type) {
+            self.item_type.set(t);
+        }
+
+        pub fn set_policy(&self, policy: IdPolicy) {
+            self.items.borrow_mut().set_policy(policy);
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct IDStore(ObjectSubclass<inner::IDStore>)
+    @implements gio::ListModel;
 }
 
-// glib::wrapper! {
-//     pub struct IDStore(ObjectSubclass<inner::IDStore>)
-//     @implements gio::ListModel;
-// }
-
-// impl IDStore {
-//     pub fn new<T: IsA<glib::Object>>() -> Self {
-//         let obj: IDStore = Object::new();
-//         obj.imp().set_type(T::static_type());
-//         obj
-//     }
-
-//     pub fn push(&self, obj: impl IsA<Object>) -> (u32, Option<Object>) {
-//         let obj: glib::Object = obj.into();
-//         self.imp().push(obj)
-//     }
-//     pub fn set(&self, id: u32, obj: impl IsA<Object>) -> Option<Object> {
-//         self.imp().set(id, obj.into())
-//     }
-//     pub fn remove(&self, id: u32) -> Option<Object> {
-//         self.imp().remove(id)
-//     }
-// }
+impl IDStore {
+    pub fn new<T: IsA<glib::Object>>() -> Self {
+        Self::with_policy::<T>(IdPolicy::default())
+    }
+
+    pub fn with_policy<T: IsA<glib::Object>>(policy: IdPolicy) -> Self {
+        let obj: IDStore = Object::new();
+        obj.imp().set_type(T::static_type());
+        obj.imp().set_policy(policy);
+        obj
+    }
+
+    /// Inserts `obj` at the top of the list and returns the id it was stored under.
+    pub fn push(&self, obj: impl IsA<Object>) -> u32 {
+        self.imp().push(obj.upcast()).get()
+    }
+    /// Hands out an id without storing anything under it, for notifications
+    /// that are answered but never shown.
+    pub fn reserve(&self) -> u32 {
+        self.imp().reserve().get()
+    }
+    /// Appends `obj` at the bottom of the list under a previously issued `id`.
+    pub fn restore(&self, id: u32, obj: impl IsA<Object>) -> bool {
+        let Some(id) = NonZeroU32::new(id) else {
+            return false;
+        };
+        self.imp().restore(id, obj.upcast())
+    }
+    /// Replaces the object stored under `id` in place, keeping its position.
+    pub fn set(&self, id: u32, obj: impl IsA<Object>) -> Option<Object> {
+        self.imp().set(NonZeroU32::new(id)?, obj.upcast())
+    }
+    pub fn remove(&self, id: u32) -> Option<Object> {
+        self.imp().remove(NonZeroU32::new(id)?)
+    }
+    pub fn get(&self, id: u32) -> Option<Object> {
+        self.imp().get(NonZeroU32::new(id)?)
+    }
+    pub fn position(&self, id: u32) -> Option<u32> {
+        self.imp().position(NonZeroU32::new(id)?).map(|p| p as u32)
+    }
+    /// Only affects ids handed out after the call.
+    pub fn set_policy(&self, policy: IdPolicy) {
+        self.imp().set_policy(policy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inner::{IdPolicy, Store};
+    use std::num::NonZeroU32;
+
+    fn id(n: u32) -> NonZeroU32 {
+        NonZeroU32::new(n).unwrap()
+    }
+
+    #[test]
+    fn monotonic_does_not_reuse_freed_ids() {
+        let mut store = Store::with_policy(IdPolicy::Monotonic);
+        assert_eq!(store.push_tail("a"), id(1));
+        assert_eq!(store.push_tail("b"), id(2));
+
+        store.remove(&id(2)).unwrap();
+        assert_eq!(store.push_tail("c"), id(3));
+    }
+
+    #[test]
+    fn monotonic_wraparound_skips_live_ids() {
+        let mut store = Store::with_policy(IdPolicy::Monotonic);
+        store.push_tail("a");
+        store.push_tail("b");
+
+        // restoring the largest id leaves the counter wrapped around to the start
+        assert!(store.insert_tail(id(u32::MAX), "max"));
+        assert_eq!(store.push_tail("c"), id(3));
+
+        store.remove(&id(1)).unwrap();
+        assert_eq!(store.push_tail("d"), id(4));
+    }
+
+    #[test]
+    fn reuse_hands_out_most_recently_freed_id() {
+        let mut store = Store::with_policy(IdPolicy::Reuse);
+        store.push_tail("a");
+        store.push_tail("b");
+        store.push_tail("c");
+
+        store.remove(&id(1)).unwrap();
+        store.remove(&id(3)).unwrap();
+        assert_eq!(store.push_tail("d"), id(3));
+        assert_eq!(store.push_tail("e"), id(1));
+        assert_eq!(store.push_tail("f"), id(4));
+    }
+
+    #[test]
+    fn insert_tail_moves_counter_past_restored_ids() {
+        let mut store = Store::with_policy(IdPolicy::Monotonic);
+        assert!(store.insert_tail(id(10), "a"));
+        assert_eq!(store.push_tail("b"), id(11));
+
+        // a smaller restored id leaves the counter alone
+        assert!(store.insert_tail(id(5), "c"));
+        assert_eq!(store.push_tail("d"), id(12));
+
+        assert!(!store.insert_tail(id(10), "taken"));
+        assert_eq!(store.ids().collect::<Vec<_>>(), [10, 11, 5, 12].map(id));
+    }
+
+    #[test]
+    fn insert_tail_takes_id_off_the_free_list() {
+        let mut store = Store::with_policy(IdPolicy::Reuse);
+        store.push_tail("a");
+        store.push_tail("b");
+        store.remove(&id(2)).unwrap();
+
+        assert!(store.insert_tail(id(2), "restored"));
+        assert_eq!(store.push_tail("c"), id(3));
+    }
+
+    #[test]
+    fn set_policy_keeps_items() {
+        let mut store = Store::with_policy(IdPolicy::Reuse);
+        store.push_tail("a");
+        store.push_tail("b");
+        store.remove(&id(1)).unwrap();
+
+        store.set_policy(IdPolicy::Monotonic);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(&id(2)), Some(&"b"));
+        assert_eq!(store.push_tail("c"), id(3));
+    }
+}
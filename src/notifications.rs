@@ -1,23 +1,29 @@
 use gtk::{
     glib::{
-        self, Object},
+        self, closure_local, Object},
+    prelude::ObjectExt,
+    prelude::{ButtonExt, WidgetExt},
     CompositeTemplate,
 };
 
+use adw::subclass::prelude::ObjectSubclassIsExt;
+
 use crate::notification_server;
 
 mod inner {
 
     use adw::subclass::bin::BinImpl;
     use gio::glib::object::CastNone;
-    use gtk::prelude::ListItemExt;
+    use gtk::prelude::{ListItemExt, ListModelExt, ToVariant};
     
 
     use gtk::glib::{self};
     use gtk::subclass::prelude::*;
     use gtk::template_callbacks;
+    use std::cell::OnceCell;
 
     use crate::notification_display;
+    use crate::notification_group::NotificationGroupHeader;
 
     use super::*;
     #[derive(CompositeTemplate, Default)]
@@ -25,6 +31,12 @@ mod inner {
     pub struct NotificationsModule {
         #[template_child(id = "view")]
         pub view: TemplateChild<gtk::ListView>,
+
+        #[template_child(id = "missed-button")]
+        pub missed_button: TemplateChild<gtk::Button>,
+
+        pub server: OnceCell<notification_server::NotificationServer>,
+        pub groups: OnceCell<notification_server::NotificationGroups>,
     }
 
     #[template_callbacks]
@@ -47,11 +59,50 @@ mod inner {
                 return;
             };
 
-            child.set_from_notification(&item); 
+            child.set_item(Some(&item));
+        }
+        #[template_callback]
+        fn on_unbind(&self, item: &gtk::ListItem) {
+            let Some(child) = item.child().and_downcast::<notification_display::NotificationDisplay>() else {return;};
+            child.set_item(None::<&notification_server::NotificationItem>);
+        }
+        #[template_callback]
+        fn on_header_setup(&self, header: &gtk::ListHeader) {
+            header.set_child(Some(&NotificationGroupHeader::new()));
+        }
+        #[template_callback]
+        fn on_header_bind(&self, header: &gtk::ListHeader) {
+            let Some(child) = header.child().and_downcast::<NotificationGroupHeader>() else {
+                return;
+            };
+            let Some(item) = header.item().and_downcast::<notification_server::NotificationItem>() else {
+                return;
+            };
+            let Some(groups) = self.groups.get() else {
+                return;
+            };
+
+            let key = item.group_key();
+            child.set_group(&key, &item, groups.group_len(&key), groups.is_expanded(&key));
         }
         #[template_callback]
         fn on_activate(listview: gtk::ListView, position: u32) {
-            println!("{:?} {}", listview, position)
+            let Some(item) = listview
+                .model()
+                .and_then(|model| model.item(position))
+                .and_downcast::<notification_server::NotificationItem>()
+            else {
+                return;
+            };
+
+            if !item.get_actions().iter().any(|(key, _)| key == "default") {
+                return;
+            }
+
+            let target = (item.id(), "default").to_variant();
+            if let Err(e) = listview.activate_action("app.invoke-action", Some(&target)) {
+                eprintln!("Could not invoke default action: {e}");
+            }
         }
     }
 
@@ -69,6 +120,17 @@ mod inner {
         fn class_init(klass: &mut Self::Class) {
             klass.bind_template();
             klass.bind_template_callbacks();
+
+            klass.install_action(
+                "notifications.toggle-group",
+                Some(glib::VariantTy::STRING),
+                |module, _, param| {
+                    let Some(key) = param.and_then(|p| p.get::<String>()) else {
+                        return;
+                    };
+                    module.toggle_group(&key);
+                },
+            );
         }
         fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
             obj.init_template();
@@ -80,13 +142,10 @@ mod inner {
     impl ObjectImpl for NotificationsModule {
         fn constructed(&self) {
             self.parent_constructed();
-            let obj = &self.obj();
-            let imp = obj.imp();
-            let server = notification_server::NotificationServer::new();
-            let store = server.get_store();
-            // let model = gtk::NoSelection::new(Some(store));
-            // imp.view.set_model(Some(&model));
-            server.connect_to_dbus();
+
+            if let Some(server) = crate::application::notification_server() {
+                self.obj().set_server(&server);
+            }
         }
     }
     impl BinImpl for NotificationsModule {}
@@ -105,4 +164,49 @@ impl NotificationsModule {
         obj
 
     }
+
+    pub fn server(&self) -> Option<notification_server::NotificationServer> {
+        self.imp().server.get().cloned()
+    }
+
+    /// Shows the notifications of `server`. Every view shares the one server
+    /// owned by the application, modules pick it up on their own when constructed.
+    pub fn set_server(&self, server: &notification_server::NotificationServer) {
+        let imp = self.imp();
+        if imp.server.set(server.clone()).is_err() {
+            return;
+        }
+
+        let groups = notification_server::NotificationGroups::new(&server.get_store());
+        let model = gtk::NoSelection::new(Some(groups.clone()));
+        imp.view.set_model(Some(&model));
+        let _ = imp.groups.set(groups);
+
+        // the handler goes away together with the module
+        server.connect_closure(
+            "dnd-changed",
+            false,
+            closure_local!(
+                #[watch(rename_to = module)]
+                self,
+                move |_: notification_server::NotificationServer, dnd: bool, missed: u32| {
+                    module.update_missed(dnd, missed);
+                }
+            ),
+        );
+        self.update_missed(server.dnd(), server.missed_count());
+    }
+
+    /// Switches a group between showing only its latest notification and all of them.
+    pub fn toggle_group(&self, key: &str) {
+        if let Some(groups) = self.imp().groups.get() {
+            groups.set_expanded(key, !groups.is_expanded(key));
+        }
+    }
+
+    fn update_missed(&self, dnd: bool, missed: u32) {
+        let missed_button = self.imp().missed_button.get();
+        missed_button.set_label(&format!("{missed} while muted"));
+        missed_button.set_visible(!dnd && missed > 0);
+    }
 }
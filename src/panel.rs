@@ -1,25 +1,44 @@
 use adw::subclass::prelude::{ObjectSubclassExt, ObjectSubclassIsExt};
-use gtk::{gio, glib::{self, Object}, prelude::GtkWindowExt};
+use gtk::{
+    gdk, gio,
+    glib::{self, clone, object::Cast, Object},
+    prelude::{GtkWindowExt, WidgetExt, WidgetExtManual},
+};
+use gtk4_layer_shell::{KeyboardMode, Layer, LayerShell};
 
 
 mod inner { 
-    use std::cell::OnceCell;
+    use std::cell::Cell;
 
     use crate::bento::BentoGrid;
 
     use super::*;
 
     use adw::subclass::{application_window::AdwApplicationWindowImpl, prelude::ObjectImplExt};
+    use gtk::glib::{derived_properties, Properties};
+    use gtk::prelude::ObjectExt;
     use gtk::subclass::{prelude::*, widget::WidgetImpl, window::WindowImpl};
     use gtk::CompositeTemplate;
-    use gtk4_layer_shell::{Edge, Layer, LayerShell};
+    use gtk4_layer_shell::Edge;
 
-    #[derive(CompositeTemplate, Default)]
+    #[derive(CompositeTemplate, Default, Properties)]
+    #[properties(wrapper_type = super::Panel)]
     #[template(resource = "/shell/ui/panel.ui")]
     pub struct Panel {
         #[template_child(id="bento")]
         pub center: TemplateChild<BentoGrid>,
-        pub notification_store: OnceCell<Option<gio::ListStore>>
+
+        #[template_child(id = "revealer")]
+        pub revealer: TemplateChild<gtk::Revealer>,
+
+        /// Whether the panel sits on the overlay layer and takes the keyboard.
+        #[property(get)]
+        pub raised: Cell<bool>,
+
+        /// Unmap the panel when it is lowered, instead of leaving it on the
+        /// bottom layer as the desktop background.
+        #[property(get, set)]
+        pub unmap_when_lowered: Cell<bool>,
     }
 
 
@@ -42,6 +61,7 @@ mod inner {
         }
     }
 
+    #[derived_properties]
     impl ObjectImpl for Panel {
         fn constructed(&self) {
             self.parent_constructed();
@@ -55,6 +75,43 @@ mod inner {
             obj.set_anchor(Edge::Top, true);
             obj.set_layer(Layer::Bottom);
 
+            let keys = gtk::EventControllerKey::new();
+            keys.connect_key_pressed(clone!(
+                #[weak]
+                obj,
+                #[upgrade_or]
+                glib::Propagation::Proceed,
+                move |_, key, _, _| {
+                    if key != gdk::Key::Escape || !obj.raised() {
+                        return glib::Propagation::Proceed;
+                    }
+                    obj.lower();
+                    glib::Propagation::Stop
+                }
+            ));
+            obj.add_controller(keys);
+
+            let click = gtk::GestureClick::new();
+            click.connect_pressed(clone!(
+                #[weak]
+                obj,
+                move |_, _, x, y| {
+                    if obj.raised() && !obj.is_on_bento(x, y) {
+                        obj.lower();
+                    }
+                }
+            ));
+            obj.add_controller(click);
+
+            self.revealer.connect_child_revealed_notify(clone!(
+                #[weak]
+                obj,
+                move |revealer| {
+                    if !revealer.is_child_revealed() && !obj.raised() {
+                        obj.finish_lowering();
+                    }
+                }
+            ));
         }
     }
     impl WidgetImpl for Panel {}
@@ -71,14 +128,99 @@ glib::wrapper! {
 }
 
 impl Panel {
-    pub fn new(app: &adw::Application, store: Option<gio::ListStore>) -> Self {
+    pub fn new(app: &adw::Application) -> Self {
         let obj: Panel = Object::new();
         obj.set_application(Some(app));
         obj
     }
 
-    fn set_server(&self, store: Option<gio::ListStore>) {
-        let inner = self.imp();
-        inner.notification_store.set(store);
+    /// Brings the panel in front of everything and grabs the keyboard, revealing
+    /// the bento grid.
+    pub fn raise(&self) {
+        let imp = self.imp();
+        if imp.raised.replace(true) {
+            return;
+        }
+
+        self.set_layer(Layer::Overlay);
+        self.set_keyboard_mode(KeyboardMode::Exclusive);
+        self.add_css_class("raised");
+
+        // a revealer only transitions while mapped, so it is revealed on the
+        // first frame after present()
+        imp.revealer.set_reveal_child(false);
+        self.present();
+        self.add_tick_callback(|obj, _| {
+            if obj.raised() {
+                obj.imp().revealer.set_reveal_child(true);
+            }
+            glib::ControlFlow::Break
+        });
+        self.notify_raised();
     }
-}
\ No newline at end of file
+
+    /// Moves the panel back to the bottom layer. When it is unmapped there the
+    /// bento grid is hidden first, and the panel unmapped once that is done.
+    pub fn lower(&self) {
+        let imp = self.imp();
+        if !imp.raised.replace(false) {
+            return;
+        }
+
+        self.set_keyboard_mode(KeyboardMode::None);
+        if self.unmap_when_lowered() {
+            imp.revealer.set_reveal_child(false);
+        } else {
+            self.finish_lowering();
+        }
+        self.notify_raised();
+    }
+
+    pub fn toggle(&self) {
+        if self.raised() {
+            self.lower();
+        } else {
+            self.raise();
+        }
+    }
+
+    fn finish_lowering(&self) {
+        self.remove_css_class("raised");
+        if self.unmap_when_lowered() {
+            self.set_visible(false);
+        }
+        self.set_layer(Layer::Bottom);
+        // left on the bottom layer the panel is the desktop, so it stays revealed
+        self.imp().revealer.set_reveal_child(!self.unmap_when_lowered());
+    }
+
+    fn is_on_bento(&self, x: f64, y: f64) -> bool {
+        let bento = self.imp().center.get();
+        self.pick(x, y, gtk::PickFlags::DEFAULT)
+            .is_some_and(|widget| &widget == bento.upcast_ref::<gtk::Widget>() || widget.is_ancestor(&bento))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[gtk::test]
+    fn lowered_desktop_panel_stays_revealed() {
+        adw::init().expect("Could not initialize libadwaita");
+        crate::load_resources();
+
+        let panel: Panel = Object::new();
+        let revealer = panel.imp().revealer.get();
+        assert!(!panel.unmap_when_lowered());
+        assert!(revealer.reveals_child());
+
+        panel.raise();
+        assert!(panel.raised());
+        panel.lower();
+        assert!(!panel.raised());
+        assert!(panel.is_visible());
+        assert!(revealer.reveals_child());
+        assert!(!panel.has_css_class("raised"));
+    }
+}
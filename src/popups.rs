@@ -0,0 +1,308 @@
+use adw::subclass::prelude::ObjectSubclassIsExt;
+use gtk::{
+    gio,
+    glib::{self, clone, closure_local, object::CastNone, Object},
+    prelude::{BoxExt, GtkWindowExt, ObjectExt, WidgetExt},
+};
+use gtk4_layer_shell::{Edge, Layer, LayerShell};
+use std::time::Duration;
+
+use crate::notification_display::NotificationDisplay;
+use crate::notification_server::{NotificationItem, NotificationServer, ServerConfig};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Corner {
+    #[default]
+    TopRight,
+    TopLeft,
+    BottomRight,
+    BottomLeft,
+}
+
+impl Corner {
+    fn edges(self) -> (Edge, Edge) {
+        match self {
+            Self::TopRight => (Edge::Top, Edge::Right),
+            Self::TopLeft => (Edge::Top, Edge::Left),
+            Self::BottomRight => (Edge::Bottom, Edge::Right),
+            Self::BottomLeft => (Edge::Bottom, Edge::Left),
+        }
+    }
+}
+
+const MARGIN: i32 = 10;
+
+mod inner {
+    use std::cell::{Cell, OnceCell, RefCell};
+
+    use super::*;
+
+    use gtk::subclass::{prelude::*, widget::WidgetImpl, window::WindowImpl};
+    use gtk::CompositeTemplate;
+
+    pub struct Popup {
+        pub id: u32,
+        pub display: NotificationDisplay,
+        pub timer: Option<glib::SourceId>,
+    }
+
+    #[derive(CompositeTemplate, Default)]
+    #[template(resource = "/shell/ui/popups.ui")]
+    pub struct PopupWindow {
+        #[template_child(id = "popup-box")]
+        pub popup_box: TemplateChild<gtk::Box>,
+
+        pub server: OnceCell<NotificationServer>,
+        pub popups: RefCell<Vec<Popup>>,
+        pub corner: Cell<Corner>,
+        pub max_visible: Cell<usize>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PopupWindow {
+        const NAME: &'static str = "PopupWindow";
+        type Type = super::PopupWindow;
+        type ParentType = gtk::ApplicationWindow;
+
+        fn new() -> Self {
+            Self {
+                max_visible: Cell::new(ServerConfig::default().popup_max_visible),
+                ..Default::default()
+            }
+        }
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for PopupWindow {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+
+            obj.init_layer_shell();
+            obj.set_layer(Layer::Overlay);
+            obj.set_corner(Corner::default());
+        }
+    }
+    impl WidgetImpl for PopupWindow {}
+    impl WindowImpl for PopupWindow {}
+    impl ApplicationWindowImpl for PopupWindow {}
+}
+
+glib::wrapper! {
+    pub struct PopupWindow(ObjectSubclass<inner::PopupWindow>)
+    @extends gtk::ApplicationWindow, gtk::Window, gtk::Widget,
+    @implements gio::ActionGroup, gio::ActionMap, gtk::Accessible, gtk::Buildable,
+                gtk::ConstraintTarget, gtk::Native, gtk::Root, gtk::ShortcutManager;
+}
+
+impl PopupWindow {
+    pub fn new(app: &adw::Application) -> Self {
+        let obj: PopupWindow = Object::new();
+        obj.set_application(Some(app));
+        if let Some(server) = crate::application::notification_server() {
+            let config = server.config();
+            obj.set_corner(config.popup_corner);
+            obj.set_max_visible(config.popup_max_visible);
+            obj.set_server(&server);
+        }
+        obj
+    }
+
+    fn set_server(&self, server: &NotificationServer) {
+        let imp = self.imp();
+        if imp.server.set(server.clone()).is_err() {
+            return;
+        }
+
+        server.connect_closure(
+            "notification-received",
+            false,
+            closure_local!(
+                #[watch(rename_to = popups)]
+                self,
+                move |_: NotificationServer, item: NotificationItem| {
+                    popups.show_notification(&item);
+                }
+            ),
+        );
+        server.connect_closure(
+            "notification-closed",
+            false,
+            closure_local!(
+                #[watch(rename_to = popups)]
+                self,
+                move |_: NotificationServer, id: u32, _reason: u32| {
+                    popups.remove_popup(id);
+                }
+            ),
+        );
+    }
+
+    pub fn set_corner(&self, corner: Corner) {
+        self.imp().corner.set(corner);
+
+        for edge in [Edge::Top, Edge::Bottom, Edge::Left, Edge::Right] {
+            self.set_anchor(edge, false);
+        }
+        let (vertical, horizontal) = corner.edges();
+        for edge in [vertical, horizontal] {
+            self.set_anchor(edge, true);
+            self.set_margin(edge, MARGIN);
+        }
+        self.imp().popup_box.set_valign(match vertical {
+            Edge::Bottom => gtk::Align::End,
+            _ => gtk::Align::Start,
+        });
+    }
+
+    /// Maximum number of popups shown at once, the oldest is dropped beyond that.
+    pub fn set_max_visible(&self, max: usize) {
+        self.imp().max_visible.set(max.max(1));
+        self.trim();
+    }
+
+    fn show_notification(&self, item: &NotificationItem) {
+        if item.silent() || item.restored() {
+            return;
+        }
+        let imp = self.imp();
+        let id = item.id();
+
+        let existing = imp
+            .popups
+            .borrow()
+            .iter()
+            .find(|p| p.id == id)
+            .map(|p| p.display.clone());
+
+        let display = existing.unwrap_or_else(|| {
+            let display = NotificationDisplay::new();
+            display.add_css_class("popup");
+            self.track_hover(&display, id);
+
+            // newest popup sits closest to the anchored edge
+            match self.imp().corner.get().edges().0 {
+                Edge::Bottom => imp.popup_box.append(&display),
+                _ => imp.popup_box.prepend(&display),
+            }
+            imp.popups.borrow_mut().push(inner::Popup {
+                id,
+                display: display.clone(),
+                timer: None,
+            });
+            display
+        });
+
+        display.set_from_notification(item);
+        self.start_timer(id);
+        self.trim();
+        self.set_visible(true);
+    }
+
+    fn track_hover(&self, display: &NotificationDisplay, id: u32) {
+        let motion = gtk::EventControllerMotion::new();
+        motion.connect_enter(clone!(
+            #[weak(rename_to = popups)]
+            self,
+            move |_, _, _| popups.set_paused(id, true)
+        ));
+        motion.connect_leave(clone!(
+            #[weak(rename_to = popups)]
+            self,
+            move |_| popups.set_paused(id, false)
+        ));
+        display.add_controller(motion);
+    }
+
+    fn set_paused(&self, id: u32, paused: bool) {
+        if let Some(server) = self.imp().server.get() {
+            server.set_expiry_paused(id, paused);
+        }
+        if paused {
+            self.stop_timer(id);
+        } else {
+            self.start_timer(id);
+        }
+    }
+
+    fn timeout_for(&self, id: u32) -> Option<Duration> {
+        let server = self.imp().server.get()?;
+        let item = server.get_store().get(id).and_downcast::<NotificationItem>()?;
+        server.expiry_for(&item)
+    }
+
+    fn start_timer(&self, id: u32) {
+        self.stop_timer(id);
+        let Some(timeout) = self.timeout_for(id) else {
+            return;
+        };
+
+        let source = glib::timeout_add_local_once(
+            timeout,
+            clone!(
+                #[weak(rename_to = popups)]
+                self,
+                move || {
+                    if let Some(popup) = popups.imp().popups.borrow_mut().iter_mut().find(|p| p.id == id) {
+                        popup.timer.take();
+                    }
+                    popups.remove_popup(id);
+                }
+            ),
+        );
+
+        if let Some(popup) = self.imp().popups.borrow_mut().iter_mut().find(|p| p.id == id) {
+            popup.timer = Some(source);
+        }
+    }
+
+    fn stop_timer(&self, id: u32) {
+        let timer = self
+            .imp()
+            .popups
+            .borrow_mut()
+            .iter_mut()
+            .find(|p| p.id == id)
+            .and_then(|p| p.timer.take());
+        if let Some(timer) = timer {
+            timer.remove();
+        }
+    }
+
+    fn trim(&self) {
+        let max = self.imp().max_visible.get();
+        let oldest: Vec<u32> = {
+            let popups = self.imp().popups.borrow();
+            let excess = popups.len().saturating_sub(max);
+            popups.iter().take(excess).map(|p| p.id).collect()
+        };
+        for id in oldest {
+            self.remove_popup(id);
+        }
+    }
+
+    fn remove_popup(&self, id: u32) {
+        let imp = self.imp();
+        let popup = {
+            let mut popups = imp.popups.borrow_mut();
+            let Some(index) = popups.iter().position(|p| p.id == id) else {
+                return;
+            };
+            popups.remove(index)
+        };
+
+        if let Some(timer) = popup.timer {
+            timer.remove();
+        }
+        imp.popup_box.remove(&popup.display);
+
+        if imp.popups.borrow().is_empty() {
+            self.set_visible(false);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use gtk::{
+    gio,
+    glib::object::{Cast, IsA},
+    prelude::{FileExt, ObjectExt, WidgetExt},
+};
+
+/// Shown when nothing better is known about a notification's application.
+pub const FALLBACK_APP_ICON: &str = "application-x-executable-symbolic";
+
+/// The first of `candidates` that can actually be drawn for `widget`: files
+/// that exist and names the icon theme knows, or the generic application icon.
+pub fn first_usable_icon(widget: &impl IsA<gtk::Widget>, candidates: Vec<gio::Icon>) -> gio::Icon {
+    let theme = gtk::IconTheme::for_display(&widget.display());
+
+    candidates
+        .into_iter()
+        .find(|icon| {
+            if let Some(file) = icon.downcast_ref::<gio::FileIcon>() {
+                return file.file().query_exists(None::<&gio::Cancellable>);
+            }
+            if icon.is::<gio::ThemedIcon>() {
+                return theme.has_gicon(icon);
+            }
+            true
+        })
+        .unwrap_or_else(|| gio::ThemedIcon::new(FALLBACK_APP_ICON).upcast())
+}